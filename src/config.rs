@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crossterm::style::Color;
+use serde::Deserialize;
+use tokio::fs;
+
+use crate::filetype::Languages;
+use crate::keymap::{self, Action, Keymap};
+use crate::theme::Theme;
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct RawConfig {
+    theme: Option<String>,
+    editor: EditorConfig,
+    colors: HashMap<String, String>,
+    filetypes: Vec<FileTypeRule>,
+    keybindings: HashMap<String, Keys>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Keys {
+    One(String),
+    Many(Vec<String>),
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EditorConfig {
+    pub quit_times: u8,
+    pub message_timeout: u64,
+    pub auto_pairs: bool,
+    pub line_numbers: LineNumbers,
+    /// Whether rows longer than the screen is wide go on below instead of scrolling sideways.
+    pub soft_wrap: bool,
+    /// The column paragraphs are reflowed to, unless `.editorconfig` sets `max_line_length`.
+    pub reflow_width: usize,
+    pub tab_bar: bool,
+    /// Whether the mouse is handled by the editor, which takes it away from the terminal's own
+    /// text selection.
+    pub mouse: bool,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LineNumbers {
+    Off,
+    Absolute,
+    /// Distances from the current line, which itself shows its absolute number.
+    Relative,
+}
+
+impl Default for EditorConfig {
+    fn default() -> Self {
+        Self {
+            quit_times: 2,
+            message_timeout: 5,
+            auto_pairs: true,
+            line_numbers: LineNumbers::Off,
+            soft_wrap: false,
+            reflow_width: 80,
+            tab_bar: false,
+            mouse: false,
+        }
+    }
+}
+
+impl EditorConfig {
+    pub fn message_timeout(&self) -> Duration {
+        Duration::from_secs(self.message_timeout)
+    }
+}
+
+/// Maps file extensions onto one of the known file types.
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct FileTypeRule {
+    pub name: String,
+    pub extensions: Vec<String>,
+}
+
+#[derive(Default)]
+pub struct Config {
+    pub path: Option<PathBuf>,
+    pub editor: EditorConfig,
+    pub theme: Theme,
+    pub languages: Languages,
+    pub keymap: Keymap,
+}
+
+impl Config {
+    /// Loads the config at `path`, or the one in the XDG config directory when no path is given.
+    /// Language definitions and themes are looked up next to it.
+    ///
+    /// Never fails: problems are collected into the returned list of errors and the affected
+    /// settings keep their defaults.
+    pub async fn load(path: Option<PathBuf>) -> (Self, Vec<String>) {
+        let explicit = path.is_some();
+        let path = path.or_else(default_path);
+        let mut config = Self {
+            path: path.clone(),
+            ..Self::default()
+        };
+        let mut errors = Vec::new();
+        let config_dir = path.as_deref().and_then(Path::parent);
+
+        if let Some(dir) = config_dir {
+            config
+                .languages
+                .load_dir(&dir.join("languages"), &mut errors)
+                .await;
+        }
+
+        let contents = match &path {
+            Some(path) => match fs::read_to_string(path).await {
+                Ok(contents) => contents,
+                Err(e) => {
+                    if explicit {
+                        errors.push(format!("{}: {}", path.display(), e));
+                    }
+                    return (config, errors);
+                },
+            },
+            None => return (config, errors),
+        };
+
+        let raw: RawConfig = match toml::from_str(&contents) {
+            Ok(raw) => raw,
+            Err(e) => {
+                errors.push(e.to_string());
+                return (config, errors);
+            },
+        };
+
+        config.editor = raw.editor;
+
+        if let Some(name) = raw.theme {
+            config.theme = Theme::load(&name, config_dir, &mut errors).await;
+        }
+
+        for (name, value) in raw.colors {
+            match parse_color(&value) {
+                Some(color) => {
+                    if !config.theme.set_color(&name, color) {
+                        errors.push(format!("colors: unknown element `{}`", name));
+                    }
+                },
+                None => errors.push(format!("colors.{}: invalid color `{}`", name, value)),
+            }
+        }
+
+        for rule in raw.filetypes {
+            if !config.languages.add_rule(&rule) {
+                errors.push(format!("filetypes: unknown file type `{}`", rule.name));
+            }
+        }
+
+        for (name, keys) in raw.keybindings {
+            if let Some(action) = Action::from_name(&name) {
+                let keys = match keys {
+                    Keys::One(key) => vec![key],
+                    Keys::Many(keys) => keys,
+                };
+
+                let mut parsed = Vec::new();
+                for key in keys {
+                    match keymap::parse_key(&key) {
+                        Some(key) => parsed.push(key),
+                        None => errors.push(format!("keybindings.{}: invalid key `{}`", name, key)),
+                    }
+                }
+                config.keymap.bind(action, parsed);
+            } else {
+                errors.push(format!("keybindings: unknown action `{}`", name));
+            }
+        }
+
+        (config, errors)
+    }
+}
+
+fn default_path() -> Option<PathBuf> {
+    let config_dir = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+
+    Some(config_dir.join("hecto").join("config.toml"))
+}
+
+/// Accepts crossterm color names (`dark_grey`), `#rrggbb` and ANSI color numbers.
+pub fn parse_color(value: &str) -> Option<Color> {
+    if let Some(hex) = value.strip_prefix('#') {
+        if hex.len() != 6 {
+            return None;
+        }
+        let channel = |range| u8::from_str_radix(hex.get(range)?, 16).ok();
+
+        return Some(Color::Rgb {
+            r: channel(0..2)?,
+            g: channel(2..4)?,
+            b: channel(4..6)?,
+        });
+    }
+
+    if let Ok(value) = value.parse::<u8>() {
+        return Some(Color::AnsiValue(value));
+    }
+
+    if value.eq_ignore_ascii_case("reset") {
+        return Some(Color::Reset);
+    }
+
+    Color::try_from(value).ok()
+}
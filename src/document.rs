@@ -1,7 +1,16 @@
+use std::cmp;
+use std::ops::Range;
+
 use tokio::fs;
-use tokio::io::{AsyncWriteExt, Result};
+use tokio::io::{AsyncWriteExt, Error, ErrorKind, Result};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 
-use crate::{FileType, Position, Row, SearchDirection};
+use crate::editorconfig::EditorSettings;
+use crate::filetype::{Languages, MODELINE_LINES};
+use crate::history::{Change, History};
+use crate::syntax_tree::{SyntaxTree, TextEdit};
+use crate::{highlighting, FileType, HighlightingOptions, Position, Row, SearchDirection};
 
 #[derive(Default)]
 pub struct Document {
@@ -9,30 +18,98 @@ pub struct Document {
     pub file_name: Option<String>,
     dirty: bool,
     file_type: FileType,
+    /// Set when the user picked the file type, which is then kept instead of detected.
+    file_type_is_manual: bool,
+    settings: EditorSettings,
+    /// Set when the file type has a tree-sitter grammar, which then replaces the highlighter.
+    syntax: Option<SyntaxTree>,
+    /// Rows above this one are highlighted and up to date.
+    highlighted_rows: usize,
+    highlighted_word: Option<String>,
+    history: History,
 }
 
 impl Document {
-    pub async fn open(file_name: &str) -> Result<Self> {
-        let contents = fs::read_to_string(file_name).await?;
-        let file_type = FileType::from(file_name);
-        let mut rows = Vec::new();
+    pub async fn open(file_name: &str, languages: &Languages) -> Result<Self> {
+        let settings = EditorSettings::resolve(file_name).await;
+        let bytes = fs::read(file_name).await?;
+        let contents = settings.charset.decode(&bytes).ok_or_else(|| {
+            Error::new(ErrorKind::InvalidData, "stream did not contain valid UTF-8")
+        })?;
+        let rows = lines(&contents).map(Row::from).collect();
 
-        for value in contents.lines() {
-            rows.push(Row::from(value));
-        }
-
-        Ok(Self {
+        let mut document = Self {
             rows,
             file_name: Some(file_name.to_owned()),
-            dirty: false,
-            file_type,
-        })
+            settings,
+            ..Self::default()
+        };
+        document.detect_file_type(languages);
+
+        Ok(document)
+    }
+
+    pub fn settings(&self) -> &EditorSettings {
+        &self.settings
+    }
+
+    pub async fn resolve_settings(&mut self) {
+        if let Some(file_name) = &self.file_name {
+            self.settings = EditorSettings::resolve(file_name).await;
+        }
     }
 
     pub fn file_type(&self) -> String {
         self.file_type.name()
     }
 
+    /// Uses `file_type` from now on, or goes back to detecting it if `None`.
+    pub fn set_file_type(&mut self, file_type: Option<FileType>, languages: &Languages) {
+        self.file_type_is_manual = file_type.is_some();
+
+        match file_type {
+            Some(file_type) => self.apply_file_type(file_type),
+            None => self.detect_file_type(languages),
+        }
+    }
+
+    /// Detects the file type again, or looks up the one picked by the user in case its
+    /// definition changed.
+    pub fn detect_file_type(&mut self, languages: &Languages) {
+        let file_type = if self.file_type_is_manual {
+            languages.find(&self.file_type.name())
+        } else {
+            let len = self.rows.len();
+            let lines: Vec<&str> = self
+                .rows
+                .iter()
+                .enumerate()
+                .filter(|(index, _)| {
+                    *index < MODELINE_LINES || index.saturating_add(MODELINE_LINES) >= len
+                })
+                .map(|(_, row)| row.as_str())
+                .collect();
+
+            Some(languages.detect(self.file_name.as_deref().unwrap_or_default(), &lines))
+        };
+
+        if let Some(file_type) = file_type {
+            self.apply_file_type(file_type);
+        }
+    }
+
+    fn apply_file_type(&mut self, file_type: FileType) {
+        if file_type.name() != self.file_type.name() {
+            self.syntax = SyntaxTree::for_file_type(&file_type.name());
+        }
+        self.file_type = file_type;
+        self.unhighlight_rows(0);
+    }
+
+    pub fn highlighting_options(&self) -> &HighlightingOptions {
+        self.file_type.highlighting_options()
+    }
+
     pub fn row(&self, index: usize) -> Option<&Row> {
         self.rows.get(index)
     }
@@ -50,17 +127,143 @@ impl Document {
             return;
         }
 
+        self.edit_syntax(at, "", "\n");
+
         if at.y == self.rows.len() {
             self.rows.push(Row::default());
+            self.record(at.y, Vec::new(), 1);
             return;
         }
 
+        let removed = self.row_strings(at.y..at.y.saturating_add(1));
         #[allow(clippy::indexing_slicing)]
         let current_row = &mut self.rows[at.y];
         let new_row = current_row.split(at.x);
 
         #[allow(clippy::integer_arithmetic)]
         self.rows.insert(at.y + 1, new_row);
+        self.record(at.y, removed, 2);
+        self.unhighlight_row(at.y);
+    }
+
+    /// Breaks the line at `at`, indenting the new row like the current one, plus one level
+    /// after an opener. A closer right after the cursor goes on a row of its own. Returns where
+    /// the cursor ends up.
+    pub fn insert_newline_indented(&mut self, at: &Position) -> Position {
+        let Some(row) = self.rows.get(at.y) else {
+            self.insert(at, '\n');
+            return Position {
+                x: 0,
+                y: at.y.saturating_add(1),
+            };
+        };
+
+        let rules = self.file_type.highlighting_options().indent();
+        let base: String = row
+            .as_str()
+            .chars()
+            .take_while(|c| *c == ' ' || *c == '\t')
+            .take(at.x)
+            .collect();
+        let code = row.code_before(at.x);
+        let opens = rules.openers.iter().any(|opener| {
+            code.strip_suffix(opener.as_str()).is_some_and(|rest| {
+                // Word openers like `then` must not be the end of a longer word
+                !opener.starts_with(|c: char| c.is_alphanumeric())
+                    || !rest.ends_with(|c: char| c.is_alphanumeric() || c == '_')
+            })
+        });
+        let before_closer = row
+            .as_str()
+            .graphemes(true)
+            .nth(at.x)
+            .is_some_and(|grapheme| rules.closers.contains(grapheme));
+
+        let mut indent = base.clone();
+        if opens {
+            indent.push_str(&self.settings.indent_unit(self.display_width(&base)));
+        }
+
+        let mut position = Position {
+            x: 0,
+            y: at.y.saturating_add(1),
+        };
+        self.insert(at, '\n');
+        for c in indent.chars() {
+            self.insert(&position, c);
+            position.x = position.x.saturating_add(1);
+        }
+
+        if opens && before_closer {
+            self.insert(&position, '\n');
+            let mut closer = Position {
+                x: 0,
+                y: position.y.saturating_add(1),
+            };
+            for c in base.chars() {
+                self.insert(&closer, c);
+                closer.x = closer.x.saturating_add(1);
+            }
+        }
+
+        position
+    }
+
+    /// Removes one level of indentation before `at` if `c` is a closer typed at the start of
+    /// the row. Returns where the cursor ends up.
+    pub fn dedent_for_closer(&mut self, at: &Position, c: char) -> Position {
+        let rules = self.file_type.highlighting_options().indent();
+        let Some(row) = self.rows.get(at.y) else {
+            return at.clone();
+        };
+        let before: String = row.as_str().graphemes(true).take(at.x).collect();
+
+        if !rules.closers.contains(c)
+            || before.is_empty()
+            || !before.chars().all(|c| c == ' ' || c == '\t')
+        {
+            return at.clone();
+        }
+
+        let removed = if before.ends_with('\t') {
+            1
+        } else {
+            let width = self.display_width(&before);
+            let size = self.settings.indent_size.max(1);
+
+            #[allow(clippy::integer_arithmetic)]
+            let removed = match width % size {
+                0 => size,
+                rest => rest,
+            };
+            before
+                .chars()
+                .rev()
+                .take_while(|c| *c == ' ')
+                .count()
+                .min(removed)
+        };
+
+        let mut position = at.clone();
+        for _ in 0..removed {
+            position.x = position.x.saturating_sub(1);
+            self.delete(&position);
+        }
+
+        position
+    }
+
+    #[allow(clippy::integer_arithmetic)]
+    fn display_width(&self, whitespace: &str) -> usize {
+        let tab_width = self.settings.tab_width.max(1);
+
+        whitespace.chars().fold(0, |width, c| {
+            if c == '\t' {
+                width + tab_width - width % tab_width
+            } else {
+                width + 1
+            }
+        })
     }
 
     pub fn insert(&mut self, at: &Position, c: char) {
@@ -73,24 +276,37 @@ impl Document {
         if c == '\n' {
             self.insert_newline(at);
         } else if at.y == self.rows.len() {
+            self.edit_syntax(at, "", &format!("{}\n", c));
             let mut row = Row::default();
             row.insert(0, c);
             self.rows.push(row);
+            self.record(at.y, Vec::new(), 1);
         } else {
+            self.edit_syntax(at, "", c.encode_utf8(&mut [0; 4]));
+            let removed = self.row_strings(at.y..at.y.saturating_add(1));
             #[allow(clippy::indexing_slicing)]
             let row = &mut self.rows[at.y];
             row.insert(at.x, c);
+            self.record(at.y, removed, 1);
         }
 
         self.unhighlight_row(at.y);
     }
 
-    pub fn unhighlight_row(&mut self, start: usize) {
-        let start = start.saturating_sub(1);
+    /// Marks a single edited row for highlighting. The rows below it are only highlighted
+    /// again if the state it ends in changes.
+    fn unhighlight_row(&mut self, index: usize) {
+        if let Some(row) = self.rows.get_mut(index) {
+            row.is_highlighted = false;
+        }
+        self.highlighted_rows = cmp::min(self.highlighted_rows, index);
+    }
 
+    fn unhighlight_rows(&mut self, start: usize) {
         for row in self.rows.iter_mut().skip(start) {
             row.is_highlighted = false;
         }
+        self.highlighted_rows = cmp::min(self.highlighted_rows, start);
     }
 
     #[allow(clippy::integer_arithmetic, clippy::indexing_slicing)]
@@ -103,27 +319,621 @@ impl Document {
         self.dirty = true;
 
         if at.x == self.rows[at.y].len() && at.y + 1 < len {
+            self.edit_syntax(at, "\n", "");
+            let removed = self.row_strings(at.y..at.y + 2);
             let next_row = self.rows.remove(at.y + 1);
-            let row = &mut self.rows[at.y];
-            row.append(&next_row);
-            row.highlight(self.file_type.highlighting_options(), &None, false);
+            self.rows[at.y].append(&next_row);
+            self.record(at.y, removed, 1);
         } else {
-            let row = &mut self.rows[at.y];
-            row.delete(at.x);
-            row.highlight(self.file_type.highlighting_options(), &None, false);
+            let row = &self.rows[at.y];
+            let start = row.byte_index(at.x);
+            let removed = row
+                .as_str()
+                .get(start..start + row.grapheme_len(at.x))
+                .unwrap_or_default()
+                .to_owned();
+            self.edit_syntax(at, &removed, "");
+
+            let removed = self.row_strings(at.y..at.y + 1);
+            self.rows[at.y].delete(at.x);
+            self.record(at.y, removed, 1);
+        }
+
+        self.unhighlight_row(at.y);
+    }
+
+    pub fn delete_range(&mut self, start: &Position, end: &Position) {
+        let Some(last) = self.rows.len().checked_sub(1) else {
+            return;
+        };
+        let end = if end.y > last {
+            Position {
+                x: self.rows.get(last).map_or(0, Row::len),
+                y: last,
+            }
+        } else {
+            end.clone()
+        };
+        if start.y > end.y || (start.y == end.y && start.x >= end.x) {
+            return;
+        }
+
+        let rows = self.row_strings(start.y..end.y.saturating_add(1));
+        let from = self
+            .rows
+            .get(start.y)
+            .map_or(0, |row| row.byte_index(start.x));
+        let to = self.rows.get(end.y).map_or(0, |row| row.byte_index(end.x));
+        let text = rows.join("\n");
+        // The end is counted from the start of the last row
+        let to = text
+            .len()
+            .saturating_sub(rows.last().map_or(0, String::len))
+            .saturating_add(to);
+        let merged = format!(
+            "{}{}",
+            text.get(..from).unwrap_or_default(),
+            text.get(to..).unwrap_or_default()
+        );
+
+        self.edit_syntax(start, text.get(from..to).unwrap_or_default(), "");
+        self.rows.splice(
+            start.y..end.y.saturating_add(1),
+            [Row::from(merged.as_str())],
+        );
+        self.dirty = true;
+        self.record(start.y, rows, 1);
+        self.unhighlight_row(start.y);
+    }
+
+    pub fn indent_rows(&mut self, rows: Range<usize>) -> Vec<(usize, usize)> {
+        let unit = self.settings.indent_unit(0);
+
+        self.edit_rows(rows, |row| {
+            if row.trim().is_empty() {
+                row.to_owned()
+            } else {
+                format!("{}{}", unit, row)
+            }
+        })
+    }
+
+    pub fn dedent_rows(&mut self, rows: Range<usize>) -> Vec<(usize, usize)> {
+        let size = self.settings.indent_size.max(1);
+
+        self.edit_rows(rows, |row| {
+            let removed = if row.starts_with('\t') {
+                1
+            } else {
+                row.chars().take(size).take_while(|c| *c == ' ').count()
+            };
+            row.get(removed..).unwrap_or_default().to_owned()
+        })
+    }
+
+    /// Uncomments `rows` if every non-blank one is commented, or comments them out. `None` if
+    /// the file type has no line comments.
+    pub fn toggle_comment(&mut self, rows: Range<usize>) -> Option<Vec<(usize, usize)>> {
+        let token = self
+            .file_type
+            .highlighting_options()
+            .line_comments()
+            .first()?
+            .clone();
+        let rows = rows.start..rows.end.min(self.rows.len());
+        let code: Vec<&str> = self
+            .rows
+            .get(rows.clone())
+            .unwrap_or_default()
+            .iter()
+            .map(Row::as_str)
+            .filter(|row| !row.trim().is_empty())
+            .collect();
+        // Only spaces and tabs count, so that every row can be split at the same byte column
+        let indent = |row: &str| {
+            row.len()
+                .saturating_sub(row.trim_start_matches([' ', '\t']).len())
+        };
+
+        let commented = code.iter().all(|row| {
+            row.get(indent(row)..)
+                .is_some_and(|rest| rest.starts_with(token.as_str()))
+        });
+        // Comment tokens line up at the least indented row
+        let column = code.iter().map(|row| indent(row)).min().unwrap_or_default();
+
+        Some(self.edit_rows(rows, |row| {
+            if row.trim().is_empty() {
+                return row.to_owned();
+            }
+
+            let (whitespace, rest) = if commented {
+                row.split_at(indent(row))
+            } else {
+                row.split_at(column.min(indent(row)))
+            };
+            if commented {
+                let rest = rest.get(token.len()..).unwrap_or_default();
+                format!("{}{}", whitespace, rest.strip_prefix(' ').unwrap_or(rest))
+            } else {
+                format!("{}{} {}", whitespace, token, rest)
+            }
+        }))
+    }
+
+    /// Rewrites each of `rows` with `edit`, as a single change. Returns the length of each row
+    /// before and after.
+    fn edit_rows<F>(&mut self, rows: Range<usize>, edit: F) -> Vec<(usize, usize)>
+    where
+        F: Fn(&str) -> String,
+    {
+        let rows = rows.start..rows.end.min(self.rows.len());
+        let edited: Vec<String> = self
+            .rows
+            .get(rows.clone())
+            .unwrap_or_default()
+            .iter()
+            .map(|row| edit(row.as_str()))
+            .collect();
+        let old_rows = self.rows.get(rows.clone()).unwrap_or_default();
+        let lens = old_rows
+            .iter()
+            .zip(&edited)
+            .map(|(row, edited)| (row.len(), edited.graphemes(true).count()))
+            .collect();
+
+        if old_rows
+            .iter()
+            .zip(&edited)
+            .any(|(row, edited)| row.as_str() != edited)
+        {
+            self.replace_rows(rows, &edited);
+        }
+
+        lens
+    }
+
+    pub fn duplicate_rows(&mut self, rows: Range<usize>) {
+        self.rewrite_rows(rows, |rows| [rows.clone(), rows].concat());
+    }
+
+    pub fn move_rows_up(&mut self, rows: Range<usize>) -> bool {
+        let rows = rows.start..rows.end.min(self.rows.len());
+        let Some(above) = rows.start.checked_sub(1) else {
+            return false;
+        };
+        if rows.is_empty() {
+            return false;
+        }
+
+        self.rewrite_rows(above..rows.end, |mut rows| {
+            rows.rotate_left(1);
+            rows
+        });
+        true
+    }
+
+    pub fn move_rows_down(&mut self, rows: Range<usize>) -> bool {
+        if rows.is_empty() || rows.end >= self.rows.len() {
+            return false;
+        }
+
+        self.rewrite_rows(rows.start..rows.end.saturating_add(1), |mut rows| {
+            rows.rotate_right(1);
+            rows
+        });
+        true
+    }
+
+    /// Joins `rows` into one, replacing the whitespace around each row break with a single
+    /// space. Returns where the last join is, if there were rows to join.
+    pub fn join_rows(&mut self, rows: Range<usize>) -> Option<Position> {
+        let rows = rows.start..rows.end.min(self.rows.len());
+        if rows.len() < 2 {
+            return None;
+        }
+
+        let mut x = 0;
+        self.rewrite_rows(rows.clone(), |rows| {
+            let mut joined = String::new();
+            for row in rows {
+                let row = if joined.is_empty() {
+                    row.as_str()
+                } else {
+                    joined.truncate(joined.trim_end().len());
+                    row.trim_start()
+                };
+                if !joined.trim().is_empty() && !row.is_empty() {
+                    joined.push(' ');
+                }
+                x = joined.graphemes(true).count();
+                joined.push_str(row);
+            }
+            vec![joined]
+        });
+
+        Some(Position { x, y: rows.start })
+    }
+
+    pub fn delete_rows(&mut self, rows: Range<usize>) {
+        self.rewrite_rows(rows, |_| Vec::new());
+    }
+
+    pub fn sort_rows(&mut self, rows: Range<usize>) {
+        self.rewrite_rows(rows, |mut rows| {
+            rows.sort();
+            rows
+        });
+    }
+
+    /// Removes the rows of `rows` that appeared earlier in it, returning how many are left.
+    pub fn unique_rows(&mut self, rows: Range<usize>) -> usize {
+        self.rewrite_rows(rows, |rows| {
+            let mut unique: Vec<String> = Vec::new();
+            for row in rows {
+                if !unique.contains(&row) {
+                    unique.push(row);
+                }
+            }
+            unique
+        })
+    }
+
+    pub fn reverse_rows(&mut self, rows: Range<usize>) {
+        self.rewrite_rows(rows, |mut rows| {
+            rows.reverse();
+            rows
+        });
+    }
+
+    /// Returns how many rows there are instead.
+    fn rewrite_rows<F>(&mut self, rows: Range<usize>, rewrite: F) -> usize
+    where
+        F: FnOnce(Vec<String>) -> Vec<String>,
+    {
+        let rows = rows.start..rows.end.min(self.rows.len());
+        let old_rows = self.row_strings(rows.clone());
+        let new_rows = rewrite(old_rows.clone());
+
+        if new_rows != old_rows {
+            self.replace_rows(rows, &new_rows);
+        }
+
+        new_rows.len()
+    }
+
+    /// Rows with text after the same comment or quote prefix, where a list item starts a
+    /// paragraph of its own. Empty if row `y` has no text.
+    pub fn paragraph(&self, y: usize) -> Range<usize> {
+        let Some(row) = self.rows.get(y) else {
+            return y..y;
+        };
+        let prefix = self.line_prefix(row.as_str()).trim();
+        let in_paragraph = |row: &Row| {
+            self.line_prefix(row.as_str()).trim() == prefix
+                && !self.is_paragraph_break(row.as_str())
+        };
+        let is_list_item = |row: &Row| self.list_marker(row.as_str()).is_some();
+
+        if !in_paragraph(row) {
+            return y..y;
+        }
+
+        let mut above = 0_usize;
+        if !is_list_item(row) {
+            for row in self.rows.iter().take(y).rev() {
+                if !in_paragraph(row) {
+                    break;
+                }
+                above = above.saturating_add(1);
+                if is_list_item(row) {
+                    break;
+                }
+            }
+        }
+        let below = self
+            .rows
+            .iter()
+            .skip(y.saturating_add(1))
+            .take_while(|row| in_paragraph(row) && !is_list_item(row))
+            .count();
+        y.saturating_sub(above)..y.saturating_add(below).saturating_add(1)
+    }
+
+    /// Every row takes the prefix of the first row of its paragraph, indented past the marker
+    /// of a list item. Returns how many rows there are instead.
+    pub fn reflow_rows(&mut self, rows: Range<usize>, width: usize) -> usize {
+        let rows = rows.start..rows.end.min(self.rows.len());
+        let old_rows = self.row_strings(rows.clone());
+        let mut new_rows = Vec::new();
+        // The prefixes of the first and the other rows of the paragraph being filled, and its
+        // words
+        let mut paragraph: Option<(String, String, Vec<String>)> = None;
+
+        for row in &old_rows {
+            let prefix = self.line_prefix(row);
+            let marker = self.list_marker(row);
+            let text = row
+                .get(prefix.len().saturating_add(marker.unwrap_or(0))..)
+                .unwrap_or_default();
+            let is_break = self.is_paragraph_break(row);
+            let continues = paragraph
+                .as_ref()
+                .is_some_and(|(_, rest, _)| rest.trim() == prefix.trim());
+
+            if is_break || marker.is_some() || !continues {
+                if let Some((first, rest, words)) = paragraph.take() {
+                    new_rows.extend(self.fill(&first, &rest, &words, width));
+                }
+            }
+            if is_break {
+                new_rows.push(row.trim_end().to_owned());
+                continue;
+            }
+
+            let (_, _, words) = paragraph.get_or_insert_with(|| {
+                let first = row
+                    .get(..row.len().saturating_sub(text.len()))
+                    .unwrap_or_default();
+                let hanging = self
+                    .display_width(first)
+                    .saturating_sub(self.display_width(prefix));
+                (
+                    first.to_owned(),
+                    format!("{}{}", prefix, " ".repeat(hanging)),
+                    Vec::new(),
+                )
+            });
+            words.extend(text.split_whitespace().map(str::to_owned));
+        }
+        if let Some((first, rest, words)) = paragraph {
+            new_rows.extend(self.fill(&first, &rest, &words, width));
+        }
+
+        let len = new_rows.len();
+        if new_rows != old_rows {
+            self.replace_rows(rows, &new_rows);
+        }
+
+        len
+    }
+
+    /// Whether `row` has no text besides its prefix, or only opens or closes a block comment.
+    fn is_paragraph_break(&self, row: &str) -> bool {
+        let text = row
+            .get(self.line_prefix(row).len()..)
+            .unwrap_or_default()
+            .trim();
+        let code = row.trim();
+
+        text.is_empty()
+            || self
+                .file_type
+                .highlighting_options()
+                .block_comments()
+                .iter()
+                .any(|comment| code == comment.start || code == comment.end)
+    }
+
+    /// Words too wide for a row of their own are left to overflow.
+    fn fill(
+        &self,
+        first_prefix: &str,
+        prefix: &str,
+        words: &[String],
+        width: usize,
+    ) -> Vec<String> {
+        let prefix_width = self.display_width(prefix);
+        let mut rows = Vec::new();
+        let mut row = first_prefix.to_owned();
+        let mut row_width = self.display_width(first_prefix);
+        let mut empty = true;
+
+        for word in words {
+            let word_width = word.width();
+            if !empty && row_width.saturating_add(1).saturating_add(word_width) > width {
+                rows.push(row.trim_end().to_owned());
+                row.clear();
+                row.push_str(prefix);
+                row_width = prefix_width;
+                empty = true;
+            }
+            if !empty {
+                row.push(' ');
+                row_width = row_width.saturating_add(1);
+            }
+            row.push_str(word);
+            row_width = row_width.saturating_add(word_width);
+            empty = false;
+        }
+        rows.push(row);
+
+        rows
+    }
+
+    /// The indentation, comment token and quotes `row` starts with. The `*` continuing block
+    /// comments such as `/* */` counts as a comment token too.
+    fn line_prefix<'a>(&self, row: &'a str) -> &'a str {
+        let opts = self.file_type.highlighting_options();
+        let mut tokens: Vec<&str> = opts.line_comments().iter().map(String::as_str).collect();
+        if opts
+            .block_comments()
+            .iter()
+            .any(|comment| comment.start.ends_with('*'))
+        {
+            tokens.push("*");
+        }
+        // Longer tokens first, so that `--` is not mistaken for `-`
+        tokens.sort_by_key(|token| cmp::Reverse(token.len()));
+
+        let code = row.trim_start();
+        let mut text = tokens
+            .iter()
+            .find_map(|token| {
+                // Doc comments like `///` and `//!` repeat or extend the token
+                code.strip_prefix(token)
+                    .map(|rest| rest.trim_start_matches(|c| token.contains(c) || c == '!'))
+            })
+            .unwrap_or(code)
+            .trim_start();
+        // Quotes can be nested, like `> >`
+        while let Some(rest) = opts
+            .paragraphs()
+            .quotes
+            .iter()
+            .find_map(|quote| text.strip_prefix(quote.as_str()))
+        {
+            text = rest.trim_start();
+        }
+
+        row.get(..row.len().saturating_sub(text.len()))
+            .unwrap_or_default()
+    }
+
+    /// The byte length of the list marker after the prefix of `row` and the whitespace
+    /// following it.
+    fn list_marker(&self, row: &str) -> Option<usize> {
+        let rules = self.file_type.highlighting_options().paragraphs();
+        let text = row.get(self.line_prefix(row).len()..)?;
+
+        let number = text
+            .len()
+            .saturating_sub(text.trim_start_matches(|c: char| c.is_ascii_digit()).len());
+        let marker =
+            if rules.numbered_lists && number > 0 && text.get(number..)?.starts_with(['.', ')']) {
+                number.saturating_add(1)
+            } else {
+                rules
+                    .list_markers
+                    .iter()
+                    .find(|marker| text.starts_with(marker.as_str()))?
+                    .len()
+            };
+
+        let rest = text.get(marker..)?;
+        let text_after = rest.trim_start();
+        let space = rest.len().saturating_sub(text_after.len());
+        (space > 0 && !text_after.is_empty()).then_some(marker.saturating_add(space))
+    }
+
+    pub fn replace_rows(&mut self, range: Range<usize>, rows: &[String]) {
+        let range = range.start.min(self.rows.len())..range.end.min(self.rows.len());
+        let removed = self.row_strings(range.clone());
+        let inserted = rows.len();
+
+        self.splice_rows(range.start, removed.len(), rows);
+        self.record(range.start, removed, inserted);
+    }
+
+    pub fn checkpoint(&mut self, cursor: &Position, typing: bool) {
+        self.history.checkpoint(cursor, typing);
+    }
+
+    pub fn set_cursor(&mut self, cursor: &Position) {
+        self.history.set_cursor(cursor);
+    }
+
+    /// Returns where the cursor was before the undone step.
+    pub fn undo(&mut self) -> Option<Position> {
+        let group = self.history.undo()?;
+        for change in group.changes.iter().rev() {
+            self.splice_rows(change.start, change.inserted.len(), &change.removed);
+        }
+
+        Some(group.cursor_before)
+    }
+
+    /// Returns where the cursor was after the redone step.
+    pub fn redo(&mut self) -> Option<Position> {
+        let group = self.history.redo()?;
+        for change in &group.changes {
+            self.splice_rows(change.start, change.removed.len(), &change.inserted);
+        }
+
+        Some(group.cursor_after)
+    }
+
+    /// Replaces `count` rows from `start` on with `rows`, without recording it.
+    fn splice_rows(&mut self, start: usize, count: usize, rows: &[String]) {
+        let start = start.min(self.rows.len());
+        let end = start.saturating_add(count).min(self.rows.len());
+
+        self.rows
+            .splice(start..end, rows.iter().map(|row| Row::from(row.as_str())));
+        self.dirty = true;
+        if let Some(syntax) = &mut self.syntax {
+            syntax.invalidate();
         }
+        self.unhighlight_row(start);
     }
 
-    pub async fn save(&mut self) -> Result<()> {
+    /// Records that the rows from `start` on that used to be `removed` are now the next
+    /// `inserted` ones.
+    fn record(&mut self, start: usize, removed: Vec<String>, inserted: usize) {
+        let inserted = self.row_strings(start..start.saturating_add(inserted));
+        self.history.record(Change {
+            start,
+            removed,
+            inserted,
+        });
+    }
+
+    fn row_strings(&self, range: Range<usize>) -> Vec<String> {
+        self.rows
+            .get(range)
+            .unwrap_or_default()
+            .iter()
+            .map(|row| row.as_str().to_owned())
+            .collect()
+    }
+
+    pub async fn save(&mut self, languages: &Languages) -> Result<()> {
         if let Some(file_name) = &self.file_name {
-            let mut file = fs::File::create(file_name).await?;
-            self.file_type = FileType::from(file_name);
+            let end_of_line = self.settings.end_of_line.as_str();
+            let len = self.rows.len();
+            let mut contents = String::new();
+            let mut first_trimmed = None;
+
+            for (index, row) in self.rows.iter_mut().enumerate() {
+                let trimmed = self
+                    .settings
+                    .trim_trailing_whitespace
+                    .then(|| row.trim_end())
+                    .flatten();
+                if let Some(trimmed) = trimmed {
+                    row.is_highlighted = false;
+                    first_trimmed = first_trimmed.or(Some(index));
+                    self.history.record(Change {
+                        start: index,
+                        removed: vec![format!("{}{}", row.as_str(), trimmed)],
+                        inserted: vec![row.as_str().to_owned()],
+                    });
+                }
+
+                contents.push_str(row.as_str());
+                if index.saturating_add(1) < len || self.settings.insert_final_newline {
+                    contents.push_str(end_of_line);
+                }
+            }
 
-            for row in &mut self.rows {
-                file.write_all(row.as_bytes()).await?;
-                file.write_all(b"\n").await?;
+            if let Some(index) = first_trimmed {
+                self.highlighted_rows = cmp::min(self.highlighted_rows, index);
+                if let Some(syntax) = &mut self.syntax {
+                    syntax.invalidate();
+                }
             }
+
+            let charset = self.settings.charset;
+            let bytes = charset.encode(&contents).map_err(|c| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("{:?} cannot be saved as {}", c, charset.name()),
+                )
+            })?;
+            let mut file = fs::File::create(file_name).await?;
+            file.write_all(&bytes).await?;
             self.dirty = false;
+            self.detect_file_type(languages);
         }
 
         Ok(())
@@ -174,25 +984,274 @@ impl Document {
         None
     }
 
-    pub fn highlight(&mut self, word: &Option<String>, until: Option<usize>) {
-        let mut start_with_comment = false;
-        let until = if let Some(until) = until {
-            if until.saturating_add(1) < self.rows.len() {
-                until.saturating_add(1)
-            } else {
-                self.rows.len()
+    /// The bracket matching the one at or just before `at`, searching only `within` these rows.
+    /// Brackets in strings and comments are ignored, so the rows should be highlighted.
+    pub fn matching_bracket(
+        &self,
+        at: &Position,
+        within: Range<usize>,
+    ) -> Option<(Position, Position)> {
+        let brackets = self.rows.get(at.y)?.brackets();
+        let &(x, c) = brackets
+            .iter()
+            .find(|(x, _)| *x == at.x)
+            .or_else(|| brackets.iter().find(|(x, _)| x.saturating_add(1) == at.x))?;
+        let bracket = Position { x, y: at.y };
+
+        let (open, close, forward) = match c {
+            '(' => ('(', ')', true),
+            '[' => ('[', ']', true),
+            '{' => ('{', '}', true),
+            ')' => (')', '(', false),
+            ']' => (']', '[', false),
+            _ => ('}', '{', false),
+        };
+
+        let mut depth = 0_usize;
+        let mut check = |x: usize, y: usize, c: char| {
+            if c == open {
+                depth = depth.saturating_add(1);
+            } else if c == close {
+                if depth == 0 {
+                    return Some(Position { x, y });
+                }
+                depth = depth.saturating_sub(1);
             }
+            None
+        };
+
+        let found = if forward {
+            (at.y..within.end.min(self.rows.len())).find_map(|y| {
+                self.rows
+                    .get(y)?
+                    .brackets()
+                    .into_iter()
+                    .find_map(|(bx, bc)| (y > at.y || bx > x).then(|| check(bx, y, bc)).flatten())
+            })
         } else {
-            self.rows.len()
+            (within.start..=at.y).rev().find_map(|y| {
+                self.rows
+                    .get(y)?
+                    .brackets()
+                    .into_iter()
+                    .rev()
+                    .find_map(|(bx, bc)| (y < at.y || bx < x).then(|| check(bx, y, bc)).flatten())
+            })
         };
 
-        #[allow(clippy::indexing_slicing)]
-        for row in &mut self.rows[..until] {
-            start_with_comment = row.highlight(
-                self.file_type.highlighting_options(),
-                word,
-                start_with_comment,
-            );
+        found.map(|found| (bracket, found))
+    }
+
+    /// Highlights at least the `visible` rows. The built-in highlighter resumes from the first
+    /// row edited since the last call, while a syntax tree only needs the visible ones.
+    pub fn highlight(&mut self, word: &Option<String>, visible: Range<usize>) {
+        if *word != self.highlighted_word {
+            self.highlighted_word.clone_from(word);
+            self.unhighlight_rows(0);
+        }
+
+        if let Some(syntax) = &mut self.syntax {
+            syntax.highlight(&mut self.rows, visible, word);
+            return;
+        }
+
+        // Rows past the first edited one are skipped for as long as they still start in the
+        // state they were highlighted with
+        let start = cmp::min(self.highlighted_rows, self.rows.len());
+        let until = cmp::min(visible.end.saturating_add(1), self.rows.len());
+        let mut state = start
+            .checked_sub(1)
+            .and_then(|index| self.rows.get(index))
+            .map_or(highlighting::State::Normal, Row::end_state);
+
+        for row in self.rows.iter_mut().take(until).skip(start) {
+            state = row.highlight(self.file_type.highlighting_options(), word, state);
+        }
+        self.highlighted_rows = cmp::max(start, until);
+    }
+
+    /// Must be called before the rows change, as positions are taken from them.
+    #[allow(clippy::integer_arithmetic)]
+    fn edit_syntax(&mut self, at: &Position, removed: &str, inserted: &str) {
+        let Some(syntax) = &mut self.syntax else {
+            return;
+        };
+
+        let column = self.rows.get(at.y).map_or(0, |row| row.byte_index(at.x));
+        let start_byte = self
+            .rows
+            .iter()
+            .take(at.y)
+            .map(|row| row.as_bytes().len() + 1)
+            .sum::<usize>()
+            + column;
+        let end_point = |text: &str| match text.rfind('\n') {
+            Some(last) => (at.y + text.matches('\n').count(), text.len() - last - 1),
+            None => (at.y, column + text.len()),
+        };
+
+        syntax.edit(&TextEdit {
+            start_byte,
+            old_end_byte: start_byte + removed.len(),
+            new_end_byte: start_byte + inserted.len(),
+            start: (at.y, column),
+            old_end: end_point(removed),
+            new_end: end_point(inserted),
+        });
+    }
+}
+
+/// Splits `contents` into lines ending in `\r\n`, `\r` or `\n`, like `str::lines` but also
+/// taking a lone `\r` as a line ending.
+fn lines(contents: &str) -> impl Iterator<Item = &str> {
+    let mut rest = Some(contents).filter(|contents| !contents.is_empty());
+
+    std::iter::from_fn(move || {
+        let current = rest?;
+        let Some(end) = current.find(['\r', '\n']) else {
+            rest = None;
+            return Some(current);
+        };
+
+        let ending = current
+            .get(end..)
+            .map_or(1, |rest| if rest.starts_with("\r\n") { 2 } else { 1 });
+        rest = current
+            .get(end.saturating_add(ending)..)
+            .filter(|rest| !rest.is_empty());
+
+        current.get(..end)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_split_on_every_line_ending() {
+        let split = |contents| lines(contents).collect::<Vec<_>>();
+
+        assert_eq!(split("a\rb\r"), ["a", "b"]);
+        assert_eq!(split("a\r\nb\nc"), ["a", "b", "c"]);
+        assert_eq!(split("a\n\r\nb"), ["a", "", "b"]);
+        assert_eq!(split("\r"), [""]);
+        assert!(split("").is_empty());
+    }
+
+    fn with_rows(rows: &[&str]) -> Document {
+        Document {
+            rows: rows.iter().map(|row| Row::from(*row)).collect(),
+            ..Document::default()
         }
     }
+
+    #[test]
+    fn delete_range_is_one_change() {
+        let mut document = with_rows(&["abc", "dé\u{301}f", "ghi"]);
+        let at = |x, y| Position { x, y };
+
+        document.delete_range(&at(1, 0), &at(2, 1));
+        assert_eq!(document.row_strings(0..2), ["af", "ghi"]);
+        assert_eq!(
+            document.history.undo().map(|group| group.changes.len()),
+            Some(1)
+        );
+
+        let mut document = with_rows(&["abc", "def"]);
+        document.delete_range(&at(1, 0), &at(2, 0));
+        assert_eq!(document.row_strings(0..2), ["ac", "def"]);
+        document.checkpoint(&at(0, 0), false);
+        document.delete_range(&at(0, 0), &at(3, 5));
+        assert_eq!(document.row_strings(0..1), [""]);
+        assert!(document.undo().is_some());
+        assert_eq!(document.row_strings(0..2), ["ac", "def"]);
+    }
+
+    fn markdown(rows: &[&str]) -> Document {
+        let languages = Languages::default();
+        let mut document = with_rows(rows);
+        document.set_file_type(languages.find("Markdown"), &languages);
+        document
+    }
+
+    #[test]
+    fn reflow_keeps_lists_and_quotes_apart() {
+        let rows = [
+            "Intro text here.",
+            "- first item",
+            "- second item",
+            "1. third",
+            "> quoted line one",
+            "> quoted two",
+        ];
+        let mut document = markdown(&rows);
+
+        assert_eq!(document.reflow_rows(0..6, 72), 5);
+        assert_eq!(
+            document.row_strings(0..5),
+            [
+                "Intro text here.",
+                "- first item",
+                "- second item",
+                "1. third",
+                "> quoted line one quoted two",
+            ]
+        );
+    }
+
+    #[test]
+    fn reflow_hangs_list_items_past_their_marker() {
+        let mut document = markdown(&[
+            "- one two three",
+            "  four",
+            "> 10) alpha beta",
+            ">     gamma delta",
+        ]);
+
+        assert_eq!(document.paragraph(1), 0..2);
+        assert_eq!(document.paragraph(0), 0..2);
+        assert_eq!(document.paragraph(3), 2..4);
+        assert_eq!(document.reflow_rows(0..4, 12), 6);
+        assert_eq!(
+            document.row_strings(0..6),
+            [
+                "- one two",
+                "  three four",
+                "> 10) alpha",
+                ">     beta",
+                ">     gamma",
+                ">     delta",
+            ]
+        );
+    }
+
+    #[test]
+    fn reflow_measures_display_width() {
+        let mut document = markdown(&["漢字 漢字 漢字 漢字"]);
+
+        document.reflow_rows(0..1, 10);
+        assert_eq!(document.row_strings(0..2), ["漢字 漢字", "漢字 漢字"]);
+    }
+
+    #[test]
+    fn toggle_comment_with_mixed_indentation() {
+        let languages = Languages::default();
+        let rows = ["    a", "\u{a0}\u{a0}b", "\u{3000}c", "\t d"];
+        let mut document = with_rows(&rows);
+        document.set_file_type(languages.find("Rust"), &languages);
+
+        document.toggle_comment(0..4);
+        assert_eq!(
+            document.row_strings(0..4),
+            ["//     a", "// \u{a0}\u{a0}b", "// \u{3000}c", "// \t d"]
+        );
+        document.toggle_comment(0..4);
+        assert_eq!(document.row_strings(0..4), rows);
+
+        let mut document = with_rows(&["  a", "    \u{3000}b"]);
+        document.set_file_type(languages.find("Rust"), &languages);
+        document.toggle_comment(0..2);
+        assert_eq!(document.row_strings(0..2), ["  // a", "  //   \u{3000}b"]);
+    }
 }
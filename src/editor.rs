@@ -1,31 +1,65 @@
+use std::cmp::Ordering;
 use std::env;
 use std::io::stdout;
+use std::mem;
+use std::ops::Range;
+use std::path::PathBuf;
 use std::time::{Duration, Instant};
 
-use crossterm::event::{KeyCode, KeyModifiers};
-use crossterm::style::Color;
+use crossterm::event::{
+    Event, KeyCode, KeyEvent, KeyModifiers, MouseButton, MouseEvent, MouseEventKind,
+};
 use crossterm::terminal::{EnterAlternateScreen, LeaveAlternateScreen};
 use crossterm::{execute, Result};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
+use crate::config::{Config, LineNumbers};
+use crate::keymap::{Action, Keymap};
+use crate::theme::UiElement;
+use crate::window::{Area, Direction, Layout, Window};
 use crate::{Document, Row, Rpc, Terminal};
 
-const STATUS_FG_COLOR: Color = Color::Black;
-const STATUS_BG_COLOR: Color = Color::White;
 const VERSION: &str = env!("CARGO_PKG_VERSION");
-const QUIT_TIMES: u8 = 2;
+const WRAP_INDICATOR: char = '↩';
+const WINDOW_SEPARATOR: char = '│';
+const DOUBLE_CLICK_TIME: Duration = Duration::from_millis(400);
+const WHEEL_LINES: usize = 3;
 
-#[derive(PartialEq, Copy, Clone)]
+#[derive(PartialEq, Copy, Clone, Debug)]
 pub enum SearchDirection {
     Forward,
     Backward,
 }
 
-#[derive(Default, Clone)]
+#[derive(Default, Clone, PartialEq, Eq)]
 pub struct Position {
     pub x: usize,
     pub y: usize,
 }
 
+impl Ord for Position {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.y, self.x).cmp(&(other.y, other.x))
+    }
+}
+
+impl PartialOrd for Position {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// A line of the screen, showing display columns `start..end` of row `y`.
+struct ScreenLine {
+    y: usize,
+    start: usize,
+    end: usize,
+    /// Whether an earlier line shows the start of the row.
+    continued: bool,
+    /// Whether the row goes on on the next line.
+    wraps: bool,
+}
+
 struct StatusMessage {
     text: String,
     time: Instant,
@@ -44,12 +78,29 @@ pub struct Editor {
     should_quit: bool,
     terminal: Terminal,
     cursor_position: Position,
+    /// The other end of the selection, which spans up to the cursor.
+    selection_anchor: Option<Position>,
     offset: Position,
+    /// How many of the lines the row at the top of the screen wraps into are scrolled past.
+    offset_wrap: usize,
     document: Document,
+    /// The documents open in any window. The one being edited is moved out into `document`,
+    /// leaving an empty placeholder in its slot.
+    buffers: Vec<Document>,
+    buffer: usize,
+    /// The windows by index. The focused one is kept in the fields above instead and is only
+    /// saved here when the focus moves away from it.
+    windows: Vec<Window>,
+    focus: usize,
+    layout: Layout,
+    area: Area,
+    tab_offset: usize,
+    last_click: Option<(Instant, Position)>,
     status_message: StatusMessage,
     quit_times: u8,
     highlighted_word: Option<String>,
     rpc: Rpc,
+    config: Config,
 }
 
 impl Editor {
@@ -58,6 +109,7 @@ impl Editor {
             die(&e);
         }
 
+        Terminal::set_mouse_capture(self.config.editor.mouse);
         self.rpc.start();
 
         loop {
@@ -76,14 +128,15 @@ impl Editor {
     }
 
     pub async fn default() -> Self {
-        let args: Vec<String> = env::args().collect();
+        let (config_path, file_path) = parse_args();
+        let (config, errors) = Config::load(config_path).await;
         let mut initial_status =
-            String::from("HELP: Ctrl-F = Find | Ctrl-S = Save | Esc/Ctrl-Q = Quit | Ctrl-O = Open");
+            config_error_message(&errors).unwrap_or_else(|| help_message(&config.keymap));
 
         let rpc;
 
-        let document = if let Some(file_path) = args.get(1) {
-            let doc = Document::open(file_path);
+        let document = if let Some(file_path) = file_path {
+            let doc = Document::open(&file_path, &config.languages);
             #[allow(clippy::unwrap_used)]
             if let Ok(doc) = doc.await {
                 rpc = Rpc::from(file_path.split('/').last().unwrap().to_owned());
@@ -105,12 +158,23 @@ impl Editor {
             should_quit: false,
             terminal: Terminal::default().expect("Failed to initialize terminal"),
             cursor_position: Position::default(),
+            selection_anchor: None,
             offset: Position::default(),
+            offset_wrap: 0,
             document,
+            buffers: vec![Document::default()],
+            buffer: 0,
+            windows: vec![Window::default()],
+            focus: 0,
+            layout: Layout::default(),
+            area: Area::default(),
+            tab_offset: 0,
+            last_click: None,
             status_message: StatusMessage::from(initial_status),
-            quit_times: QUIT_TIMES,
+            quit_times: config.editor.quit_times,
             highlighted_word: None,
             rpc,
+            config,
         }
     }
 
@@ -119,28 +183,44 @@ impl Editor {
         Terminal::cursor_position(&Position::default());
 
         if self.should_quit {
+            Terminal::set_mouse_capture(false);
             execute!(stdout(), LeaveAlternateScreen)?;
             println!("Goodbye.\r");
+            Terminal::cursor_show();
         } else {
-            self.document.highlight(
-                &self.highlighted_word,
-                Some(
-                    self.offset
-                        .y
-                        .saturating_add(self.terminal.size().height as usize),
-                ),
-            );
+            let separators = self.arrange();
+            if self.is_too_small() {
+                self.draw_too_small();
+                return Terminal::flush();
+            }
+            let focus = self.focus;
 
-            self.draw_rows();
-            self.draw_status_bar();
+            for window in self.layout.windows() {
+                self.focus_window(window);
+                self.document.highlight(
+                    &self.highlighted_word,
+                    self.offset.y..self.offset.y.saturating_add(self.text_height()),
+                );
+
+                self.draw_rows();
+                self.draw_status_bar(window == focus);
+            }
+            self.focus_window(focus);
+
+            if self.config.editor.tab_bar {
+                self.scroll_tabs();
+                self.draw_tab_bar();
+            }
+            self.draw_separators(&separators);
             self.draw_message_bar();
-            Terminal::cursor_position(&Position {
-                x: self.cursor_position.x.saturating_sub(self.offset.x),
-                y: self.cursor_position.y.saturating_sub(self.offset.y),
-            });
+
+            // The cursor stays hidden while scrolled out of view
+            if let Some(position) = self.cursor_screen_position() {
+                Terminal::cursor_position(&position);
+                Terminal::cursor_show();
+            }
         }
 
-        Terminal::cursor_show();
         Terminal::flush()
     }
 
@@ -154,9 +234,12 @@ impl Editor {
             }
 
             self.document.file_name = new_name;
+            self.document.resolve_settings().await;
         }
 
-        if self.document.save().await.is_ok() {
+        if let Err(error) = self.document.save(&self.config.languages).await {
+            self.status_message = StatusMessage::from(format!("Error writing file: {}", error));
+        } else {
             self.rpc.file_name(
                 self.document
                     .file_name
@@ -164,9 +247,9 @@ impl Editor {
                     .unwrap_or_else(|| "No name".to_owned()),
             );
 
+            // Trimming trailing whitespace may have shortened the current row
+            self.move_cursor(KeyCode::Null);
             self.status_message = StatusMessage::from("File successfully saved.".to_owned());
-        } else {
-            self.status_message = StatusMessage::from("Error writing file!".to_owned());
         }
     }
 
@@ -187,7 +270,6 @@ impl Editor {
                             moved = true;
                         },
                         KeyCode::Left | KeyCode::Up => direction = SearchDirection::Backward,
-                        KeyCode::Esc => editor.document.unhighlight_row(0),
                         _ => direction = SearchDirection::Forward,
                     }
 
@@ -215,32 +297,681 @@ impl Editor {
         self.highlighted_word = None;
     }
 
+    async fn reload_config(&mut self) {
+        let (config, errors) = Config::load(self.config.path.clone()).await;
+        self.config = config;
+        self.quit_times = self.config.editor.quit_times;
+        Terminal::set_mouse_capture(self.config.editor.mouse);
+
+        self.document.detect_file_type(&self.config.languages);
+        for document in &mut self.buffers {
+            document.detect_file_type(&self.config.languages);
+        }
+
+        self.status_message = StatusMessage::from(
+            config_error_message(&errors).unwrap_or_else(|| "Config reloaded.".to_owned()),
+        );
+    }
+
+    fn set_file_type(&mut self) {
+        let mut cancelled = false;
+        let name = self
+            .prompt("Set file type (empty to detect): ", |_, key, _| {
+                cancelled = key == KeyCode::Esc;
+            })
+            .unwrap_or(None);
+        if cancelled {
+            return;
+        }
+
+        let Some(name) = name else {
+            self.document.set_file_type(None, &self.config.languages);
+            self.status_message = StatusMessage::from(format!(
+                "File type detected as {}.",
+                self.document.file_type()
+            ));
+            return;
+        };
+
+        if let Some(file_type) = self.config.languages.find(&name) {
+            self.document
+                .set_file_type(Some(file_type), &self.config.languages);
+            self.status_message =
+                StatusMessage::from(format!("File type set to {}.", self.document.file_type()));
+        } else {
+            self.status_message = StatusMessage::from(format!("Unknown file type: {}", name));
+        }
+    }
+
+    fn jump_to_bracket(&mut self) {
+        // The whole document has to be highlighted to tell which brackets are in comments
+        let len = self.document.len();
+        self.document.highlight(&self.highlighted_word, 0..len);
+
+        if let Some((_, found)) = self
+            .document
+            .matching_bracket(&self.cursor_position, 0..len)
+        {
+            self.cursor_position = found;
+        } else {
+            self.status_message = StatusMessage::from("No matching bracket.".to_owned());
+        }
+    }
+
+    fn toggle_comment(&mut self) {
+        let rows = self.selected_rows();
+        if let Some(lens) = self.document.toggle_comment(rows.clone()) {
+            self.shift_rows(rows.start, &lens);
+        } else {
+            self.status_message = StatusMessage::from(format!(
+                "{} has no line comments.",
+                self.document.file_type()
+            ));
+        }
+    }
+
+    fn undo(&mut self) {
+        if let Some(position) = self.document.undo() {
+            self.cursor_position = position;
+            self.selection_anchor = None;
+            self.move_cursor(KeyCode::Null);
+        } else {
+            self.status_message = StatusMessage::from("Nothing to undo.".to_owned());
+        }
+    }
+
+    fn redo(&mut self) {
+        if let Some(position) = self.document.redo() {
+            self.cursor_position = position;
+            self.selection_anchor = None;
+            self.move_cursor(KeyCode::Null);
+        } else {
+            self.status_message = StatusMessage::from("Nothing to redo.".to_owned());
+        }
+    }
+
+    fn duplicate_lines(&mut self) {
+        let rows = self.selected_rows();
+        self.document.duplicate_rows(rows.clone());
+        self.shift_lines(|y| y.saturating_add(rows.len()));
+    }
+
+    fn move_lines_up(&mut self) {
+        if self.document.move_rows_up(self.selected_rows()) {
+            self.shift_lines(|y| y.saturating_sub(1));
+        }
+    }
+
+    fn move_lines_down(&mut self) {
+        if self.document.move_rows_down(self.selected_rows()) {
+            self.shift_lines(|y| y.saturating_add(1));
+        }
+    }
+
+    fn join_lines(&mut self) {
+        let mut rows = self.selected_rows();
+        if rows.len() < 2 {
+            rows.end = rows.start.saturating_add(2);
+        }
+
+        if let Some(position) = self.document.join_rows(rows) {
+            self.cursor_position = position;
+            self.selection_anchor = None;
+        }
+    }
+
+    fn delete_lines(&mut self) {
+        let rows = self.selected_rows();
+        self.document.delete_rows(rows.clone());
+        self.cursor_position.y = rows.start;
+        self.selection_anchor = None;
+        self.move_cursor(KeyCode::Null);
+    }
+
+    fn reflow(&mut self) {
+        let rows = if self.selection().is_some() {
+            self.selected_rows()
+        } else {
+            self.document.paragraph(self.cursor_position.y)
+        };
+        if rows.is_empty() {
+            self.status_message = StatusMessage::from("Nothing to reflow.".to_owned());
+            return;
+        }
+
+        let width = self
+            .document
+            .settings()
+            .max_line_length
+            .unwrap_or(self.config.editor.reflow_width);
+        let len = self.document.reflow_rows(rows.clone(), width);
+
+        self.selection_anchor = None;
+        self.cursor_position = Position {
+            x: 0,
+            y: rows.start.saturating_add(len).saturating_sub(1),
+        };
+        self.move_cursor(KeyCode::End);
+    }
+
+    fn shift_lines<F>(&mut self, shift: F)
+    where
+        F: Fn(usize) -> usize,
+    {
+        self.cursor_position.y = shift(self.cursor_position.y);
+        if let Some(anchor) = &mut self.selection_anchor {
+            anchor.y = shift(anchor.y);
+        }
+    }
+
+    fn select_rows(&mut self, start: usize, len: usize) {
+        if len < 2 {
+            self.selection_anchor = None;
+            self.move_cursor(KeyCode::Null);
+            return;
+        }
+
+        self.selection_anchor = Some(Position { x: 0, y: start });
+        self.cursor_position = Position {
+            x: 0,
+            y: start.saturating_add(len),
+        };
+    }
+
+    fn arrange(&mut self) -> Vec<Area> {
+        let size = self.terminal.size();
+        // The tab bar takes up the top line
+        let top = usize::from(self.config.editor.tab_bar);
+        let (areas, separators) = self.layout.arrange(Area {
+            x: 0,
+            y: top,
+            width: size.width as usize,
+            height: (size.height as usize).saturating_sub(top),
+        });
+
+        for (window, area) in areas {
+            if window == self.focus {
+                self.area = area;
+            } else if let Some(window) = self.windows.get_mut(window) {
+                window.area = area;
+            }
+        }
+
+        separators
+    }
+
+    fn resize(&mut self) {
+        self.terminal.update_size();
+        self.arrange();
+
+        let focus = self.focus;
+        for window in self.layout.windows() {
+            self.focus_window(window);
+            self.scroll();
+        }
+        self.focus_window(focus);
+
+        Terminal::clear_screen();
+    }
+
+    fn is_too_small(&mut self) -> bool {
+        let focus = self.focus;
+        let too_small = self.layout.windows().into_iter().any(|window| {
+            self.focus_window(window);
+            self.text_height() == 0 || self.text_width() == 0
+        });
+        self.focus_window(focus);
+
+        too_small
+    }
+
+    fn focus_window(&mut self, window: usize) {
+        self.save_window();
+        let Some(next) = self.windows.get(window).cloned() else {
+            return;
+        };
+
+        self.focus = window;
+        self.load_buffer(next.buffer);
+        self.cursor_position = next.cursor_position;
+        self.selection_anchor = next.selection_anchor;
+        self.offset = next.offset;
+        self.offset_wrap = next.offset_wrap;
+        self.area = next.area;
+        self.clamp_cursor();
+    }
+
+    fn save_window(&mut self) {
+        if let Some(window) = self.windows.get_mut(self.focus) {
+            *window = Window {
+                buffer: self.buffer,
+                cursor_position: self.cursor_position.clone(),
+                selection_anchor: self.selection_anchor.clone(),
+                offset: self.offset.clone(),
+                offset_wrap: self.offset_wrap,
+                area: self.area,
+            };
+        }
+    }
+
+    fn load_buffer(&mut self, buffer: usize) {
+        if buffer >= self.buffers.len() {
+            return;
+        }
+
+        if let Some(slot) = self.buffers.get_mut(self.buffer) {
+            mem::swap(slot, &mut self.document);
+        }
+        self.buffer = buffer;
+        if let Some(slot) = self.buffers.get_mut(buffer) {
+            mem::swap(slot, &mut self.document);
+        }
+    }
+
+    fn buffer(&self, buffer: usize) -> Option<&Document> {
+        if buffer == self.buffer {
+            Some(&self.document)
+        } else {
+            self.buffers.get(buffer)
+        }
+    }
+
+    /// Keeps the cursor and the selection within the document, which may have been edited
+    /// from another window.
+    fn clamp_cursor(&mut self) {
+        let document = &self.document;
+        let clamp = |position: &mut Position| {
+            position.y = position.y.min(document.len());
+            position.x = position.x.min(document.row(position.y).map_or(0, Row::len));
+        };
+
+        clamp(&mut self.cursor_position);
+        if let Some(anchor) = &mut self.selection_anchor {
+            clamp(anchor);
+        }
+    }
+
+    fn show_buffer(&mut self, buffer: usize) {
+        if buffer == self.buffer {
+            return;
+        }
+
+        self.load_buffer(buffer);
+        self.cursor_position = Position::default();
+        self.selection_anchor = None;
+        self.offset = Position::default();
+        self.offset_wrap = 0;
+        self.update_rpc();
+    }
+
+    fn update_rpc(&mut self) {
+        let file_name = self
+            .document
+            .file_name
+            .as_deref()
+            .map_or("No name", base_name);
+        self.rpc.file_name(file_name.to_owned());
+    }
+
+    fn split_window(&mut self, direction: Direction) {
+        self.save_window();
+        let new = self.windows.len();
+        let window = self.windows.get(self.focus).cloned().unwrap_or_default();
+
+        self.windows.push(window);
+        self.layout.split(self.focus, new, direction);
+        self.focus_window(new);
+        self.arrange();
+    }
+
+    fn close_window(&mut self) {
+        let order = self.layout.windows();
+        let closed = self.focus;
+        let Some(index) = order.iter().position(|window| *window == closed) else {
+            return;
+        };
+        let Some(next) = index
+            .checked_sub(1)
+            .and_then(|index| order.get(index))
+            .or_else(|| order.get(index.saturating_add(1)))
+        else {
+            self.status_message = StatusMessage::from("Cannot close the last window.".to_owned());
+            return;
+        };
+
+        self.focus_window(*next);
+        self.windows.remove(closed);
+        self.layout.remove(closed);
+        if self.focus > closed {
+            self.focus = self.focus.saturating_sub(1);
+        }
+
+        self.arrange();
+        self.update_rpc();
+    }
+
+    fn cycle_window(&mut self, forward: bool) {
+        let order = self.layout.windows();
+        let Some(index) = order.iter().position(|window| *window == self.focus) else {
+            return;
+        };
+        let next = if forward {
+            order.get(index.saturating_add(1)).or(order.first())
+        } else {
+            index
+                .checked_sub(1)
+                .and_then(|index| order.get(index))
+                .or(order.last())
+        };
+
+        if let Some(window) = next {
+            self.focus_window(*window);
+            self.update_rpc();
+        }
+    }
+
+    fn resize_window(&mut self, grow: bool) {
+        if self.layout.resize(self.focus, grow) {
+            self.arrange();
+        } else {
+            self.status_message = StatusMessage::from("There is only one window.".to_owned());
+        }
+    }
+
+    fn cycle_tab(&mut self, forward: bool) {
+        let buffer = if forward {
+            Some(self.buffer.saturating_add(1)).filter(|buffer| *buffer < self.buffers.len())
+        } else {
+            self.buffer.checked_sub(1)
+        };
+        let last = self.buffers.len().saturating_sub(1);
+
+        self.show_buffer(buffer.unwrap_or(if forward { 0 } else { last }));
+    }
+
+    fn move_tab(&mut self, left: bool) {
+        let other = if left {
+            self.buffer.checked_sub(1)
+        } else {
+            Some(self.buffer.saturating_add(1)).filter(|buffer| *buffer < self.buffers.len())
+        };
+        let Some(other) = other else {
+            return;
+        };
+
+        self.buffers.swap(self.buffer, other);
+        for window in &mut self.windows {
+            if window.buffer == self.buffer {
+                window.buffer = other;
+            } else if window.buffer == other {
+                window.buffer = self.buffer;
+            }
+        }
+        self.buffer = other;
+    }
+
+    fn is_dirty(&self) -> bool {
+        self.document.is_dirty() || self.buffers.iter().any(Document::is_dirty)
+    }
+
+    async fn process_action(&mut self, action: Action) -> Result<()> {
+        match action {
+            Action::Quit => self.should_quit = true,
+            Action::Save => self.save().await,
+            Action::Find => self.search(),
+            Action::Open => self.open().await?,
+            Action::ReloadConfig => self.reload_config().await,
+            Action::SetFileType => self.set_file_type(),
+            Action::JumpToBracket => self.jump_to_bracket(),
+            Action::ToggleComment => self.toggle_comment(),
+            Action::Undo => self.undo(),
+            Action::Redo => self.redo(),
+            Action::DuplicateLine => self.duplicate_lines(),
+            Action::MoveLineUp => self.move_lines_up(),
+            Action::MoveLineDown => self.move_lines_down(),
+            Action::JoinLines => self.join_lines(),
+            Action::DeleteLine => self.delete_lines(),
+            Action::SortLines => {
+                let rows = self.selected_rows();
+                self.document.sort_rows(rows.clone());
+                self.select_rows(rows.start, rows.len());
+            },
+            Action::UniqueLines => {
+                let rows = self.selected_rows();
+                let len = self.document.unique_rows(rows.clone());
+                self.select_rows(rows.start, len);
+            },
+            Action::ReverseLines => {
+                let rows = self.selected_rows();
+                self.document.reverse_rows(rows.clone());
+                self.select_rows(rows.start, rows.len());
+            },
+            Action::Reflow => self.reflow(),
+            Action::SplitDown => self.split_window(Direction::Down),
+            Action::SplitRight => self.split_window(Direction::Right),
+            Action::CloseWindow => self.close_window(),
+            Action::NextWindow => self.cycle_window(true),
+            Action::PreviousWindow => self.cycle_window(false),
+            Action::GrowWindow => self.resize_window(true),
+            Action::ShrinkWindow => self.resize_window(false),
+            Action::NextTab => self.cycle_tab(true),
+            Action::PreviousTab => self.cycle_tab(false),
+            Action::MoveTabLeft => self.move_tab(true),
+            Action::MoveTabRight => self.move_tab(false),
+        }
+
+        Ok(())
+    }
+
     #[allow(clippy::integer_arithmetic)]
     async fn process_keypress(&mut self) -> Result<()> {
-        let pressed_key = Terminal::read_key()?;
-        match (pressed_key.modifiers, pressed_key.code) {
-            (KeyModifiers::CONTROL, KeyCode::Char('q')) | (_, KeyCode::Esc) => {
-                if self.quit_times > 0 && self.document.is_dirty() {
-                    self.status_message = StatusMessage::from(format!(
-                        "WARNING! File has unsaved changes. Press Ctrl-Q {} more times to quit.",
-                        self.quit_times
-                    ));
-                    self.quit_times -= 1;
-                    return Ok(());
-                }
+        let pressed_key = match Terminal::read_event()? {
+            Event::Key(key) => key,
+            Event::Mouse(event) => {
+                self.document.checkpoint(&self.cursor_position, false);
+                self.process_mouse(event);
+                self.arrange();
+                return Ok(());
+            },
+            Event::Resize(..) => {
+                self.resize();
+                return Ok(());
+            },
+        };
+        let action = self.config.keymap.action(&pressed_key);
+        // A run of typed characters is undone at once
+        let typing = action.is_none() && matches!(pressed_key.code, KeyCode::Char(_));
+        self.document.checkpoint(&self.cursor_position, typing);
+
+        if let Some(action) = action {
+            if action == Action::Quit && self.quit_times > 0 && self.is_dirty() {
+                self.status_message = StatusMessage::from(format!(
+                    "WARNING! File has unsaved changes. Quit {} more times to discard them.",
+                    self.quit_times
+                ));
+                self.quit_times -= 1;
+                return Ok(());
+            }
+
+            self.process_action(action).await?;
+        } else {
+            self.process_key(pressed_key);
+        }
+
+        self.document.set_cursor(&self.cursor_position);
+        self.arrange();
+        self.scroll();
 
-                self.should_quit = true;
+        if self.quit_times < self.config.editor.quit_times {
+            self.quit_times = self.config.editor.quit_times;
+            self.status_message = StatusMessage::from(String::new());
+        }
+
+        Ok(())
+    }
+
+    fn process_mouse(&mut self, event: MouseEvent) {
+        let column = event.column as usize;
+        let row = event.row as usize;
+
+        match event.kind {
+            MouseEventKind::Down(MouseButton::Left) if self.config.editor.tab_bar && row == 0 => {
+                if let Some((buffer, _)) = self
+                    .tab_columns()
+                    .into_iter()
+                    .find(|(_, columns)| columns.contains(&column))
+                {
+                    self.show_buffer(buffer);
+                }
             },
-            (KeyModifiers::CONTROL, KeyCode::Char('s')) => self.save().await,
-            (KeyModifiers::CONTROL, KeyCode::Char('f')) => self.search(),
-            (KeyModifiers::CONTROL, KeyCode::Char('o')) => self.open().await?,
+            MouseEventKind::Down(MouseButton::Left) => self.click(column, row),
+            MouseEventKind::Drag(MouseButton::Left) => self.drag(column, row),
+            MouseEventKind::ScrollUp => self.scroll_window_at(column, row, true),
+            MouseEventKind::ScrollDown => self.scroll_window_at(column, row, false),
+            _ => (),
+        }
+    }
 
-            (_, KeyCode::Char(c)) => {
-                self.document.insert(&self.cursor_position, c);
-                self.move_cursor(KeyCode::Right);
+    fn window_at(&self, column: usize, row: usize) -> Option<usize> {
+        let contains = |area: &Area| {
+            (area.x..area.x.saturating_add(area.width)).contains(&column)
+                && (area.y..area.y.saturating_add(area.height)).contains(&row)
+        };
+
+        if contains(&self.area) {
+            return Some(self.focus);
+        }
+        self.windows
+            .iter()
+            .enumerate()
+            .find(|(index, window)| *index != self.focus && contains(&window.area))
+            .map(|(index, _)| index)
+    }
+
+    fn click(&mut self, column: usize, row: usize) {
+        let Some(window) = self.window_at(column, row) else {
+            return;
+        };
+        if window != self.focus {
+            self.focus_window(window);
+            self.update_rpc();
+        }
+
+        // Clicking the status bar only focuses the window
+        self.selection_anchor = None;
+        if row >= self.area.y.saturating_add(self.text_height()) {
+            return;
+        }
+
+        self.cursor_position = self.position_at(column, row);
+        let is_double_click = self.last_click.as_ref().is_some_and(|(time, position)| {
+            time.elapsed() < DOUBLE_CLICK_TIME && *position == self.cursor_position
+        });
+        if is_double_click {
+            self.select_word();
+            self.last_click = None;
+        } else {
+            self.last_click = Some((Instant::now(), self.cursor_position.clone()));
+        }
+
+        self.scroll();
+    }
+
+    fn drag(&mut self, column: usize, row: usize) {
+        if self.text_height() == 0 {
+            return;
+        }
+        let top = self.area.y;
+        let bottom = top.saturating_add(self.text_height()).saturating_sub(1);
+
+        if self.selection_anchor.is_none() {
+            self.selection_anchor = Some(self.cursor_position.clone());
+        }
+        self.cursor_position = self.position_at(column, row.clamp(top, bottom));
+        if row < top {
+            self.move_cursor(KeyCode::Up);
+        } else if row > bottom {
+            self.move_cursor(KeyCode::Down);
+        }
+
+        self.scroll();
+    }
+
+    fn select_word(&mut self) {
+        let Position { x, y } = self.cursor_position;
+        let Some(row) = self.document.row(y) else {
+            return;
+        };
+        if !is_word(row.grapheme(x)) {
+            return;
+        }
+
+        let start = (0..x)
+            .rev()
+            .find(|at| !is_word(row.grapheme(*at)))
+            .map_or(0, |at| at.saturating_add(1));
+        let end = (x..row.len())
+            .find(|at| !is_word(row.grapheme(*at)))
+            .unwrap_or(row.len());
+
+        self.selection_anchor = Some(Position { x: start, y });
+        self.cursor_position = Position { x: end, y };
+    }
+
+    fn scroll_window_at(&mut self, column: usize, row: usize, up: bool) {
+        let Some(window) = self.window_at(column, row) else {
+            return;
+        };
+        let focus = self.focus;
+
+        self.focus_window(window);
+        for _ in 0..WHEEL_LINES {
+            self.scroll_line(up);
+        }
+        self.focus_window(focus);
+    }
+
+    fn scroll_line(&mut self, up: bool) {
+        if up {
+            if let Some(line) = self.offset_wrap.checked_sub(1) {
+                self.offset_wrap = line;
+            } else if let Some(y) = self.offset.y.checked_sub(1) {
+                self.offset.y = y;
+                self.offset_wrap = self.wrap_points(y).len().saturating_sub(1);
+            }
+        } else if self.offset_wrap.saturating_add(1) < self.wrap_points(self.offset.y).len() {
+            self.offset_wrap = self.offset_wrap.saturating_add(1);
+        } else if self.offset.y.saturating_add(1) < self.document.len() {
+            self.offset.y = self.offset.y.saturating_add(1);
+            self.offset_wrap = 0;
+        }
+    }
+
+    fn process_key(&mut self, pressed_key: KeyEvent) {
+        match (pressed_key.modifiers, pressed_key.code) {
+            (_, KeyCode::Char(c)) => self.type_char(c),
+            (_, KeyCode::Tab) if self.selection().is_some() => {
+                let rows = self.selected_rows();
+                let lens = self.document.indent_rows(rows.clone());
+                self.shift_rows(rows.start, &lens);
+            },
+            (_, KeyCode::BackTab) => {
+                let rows = self.selected_rows();
+                let lens = self.document.dedent_rows(rows.clone());
+                self.shift_rows(rows.start, &lens);
+            },
+            (_, KeyCode::Tab) => {
+                let indent = self
+                    .document
+                    .settings()
+                    .indent_unit(self.column(self.cursor_position.x, self.cursor_position.y));
+                for c in indent.chars() {
+                    self.document.insert(&self.cursor_position, c);
+                    self.move_cursor(KeyCode::Right);
+                }
             },
             (
-                _,
+                modifiers,
                 KeyCode::Up
                 | KeyCode::Down
                 | KeyCode::Left
@@ -250,38 +981,171 @@ impl Editor {
                 | KeyCode::End
                 | KeyCode::Home,
             ) => {
+                if modifiers.contains(KeyModifiers::SHIFT) {
+                    if self.selection_anchor.is_none() {
+                        self.selection_anchor = Some(self.cursor_position.clone());
+                    }
+                } else {
+                    self.selection_anchor = None;
+                }
                 self.move_cursor(pressed_key.code);
             },
-            (_, KeyCode::Delete) => self.document.delete(&self.cursor_position),
-            (_, KeyCode::Backspace) => {
-                if self.cursor_position.x > 0 || self.cursor_position.y > 0 {
-                    self.move_cursor(KeyCode::Left);
+            (_, KeyCode::Delete) => {
+                if !self.delete_selection() {
                     self.document.delete(&self.cursor_position);
                 }
             },
+            (_, KeyCode::Backspace) => {
+                if !self.delete_selection() {
+                    self.backspace();
+                }
+            },
             (_, KeyCode::Enter) => {
-                self.document.insert(&self.cursor_position, '\n');
-                self.cursor_position.x = 0;
-                self.cursor_position.y += 1;
+                self.delete_selection();
+                self.cursor_position = self.document.insert_newline_indented(&self.cursor_position);
             },
             _ => (),
         }
+    }
 
-        self.scroll();
+    fn selection(&self) -> Option<(Position, Position)> {
+        let anchor = self.selection_anchor.clone()?;
+        let cursor = self.cursor_position.clone();
 
-        if self.quit_times < QUIT_TIMES {
-            self.quit_times = QUIT_TIMES;
-            self.status_message = StatusMessage::from(String::new());
+        match anchor.cmp(&cursor) {
+            Ordering::Less => Some((anchor, cursor)),
+            Ordering::Greater => Some((cursor, anchor)),
+            Ordering::Equal => None,
         }
+    }
 
-        Ok(())
+    /// The rows the selection spans, leaving out the last one if it ends at its start, or
+    /// the row of the cursor.
+    fn selected_rows(&self) -> Range<usize> {
+        match self.selection() {
+            Some((start, end)) if end.x == 0 => start.y..end.y,
+            Some((start, end)) => start.y..end.y.saturating_add(1),
+            None => self.cursor_position.y..self.cursor_position.y.saturating_add(1),
+        }
+    }
+
+    /// Moves the cursor and the selection anchor along with the text of rows that changed
+    /// length, given as `(before, after)` from row `start` on.
+    fn shift_rows(&mut self, start: usize, lens: &[(usize, usize)]) {
+        let shift = |position: &mut Position| {
+            if let Some((before, after)) = position
+                .y
+                .checked_sub(start)
+                .and_then(|index| lens.get(index))
+            {
+                position.x = position.x.saturating_add(*after).saturating_sub(*before);
+            }
+        };
+
+        shift(&mut self.cursor_position);
+        if let Some(anchor) = &mut self.selection_anchor {
+            shift(anchor);
+        }
+    }
+
+    fn delete_selection(&mut self) -> bool {
+        let selection = self.selection();
+        self.selection_anchor = None;
+
+        let Some((start, end)) = selection else {
+            return false;
+        };
+        self.document.delete_range(&start, &end);
+        self.cursor_position = start;
+
+        true
+    }
+
+    fn type_char(&mut self, c: char) {
+        let auto_pairs = self.config.editor.auto_pairs;
+        let opts = self.document.highlighting_options();
+        let closer = opts.closing_pair(c).filter(|_| auto_pairs);
+        let is_closer = auto_pairs && opts.opening_pair(c).is_some();
+
+        if let Some(closer) = closer {
+            if let Some((start, end)) = self.selection() {
+                self.wrap_selection(&start, &end, c, closer);
+                return;
+            }
+        }
+        self.delete_selection();
+
+        let Position { x, y } = self.cursor_position;
+        let row = self.document.row(y);
+        let next = row.and_then(|row| row.grapheme(x));
+
+        // Typing a closer right before the same one steps over it instead
+        if is_closer && next == Some(c.encode_utf8(&mut [0; 4])) {
+            self.move_cursor(KeyCode::Right);
+            return;
+        }
+
+        if let Some(closer) = closer {
+            if row.is_none_or(|row| should_pair(row, x, c, closer)) {
+                self.document.insert(&self.cursor_position, c);
+                self.move_cursor(KeyCode::Right);
+                self.document.insert(&self.cursor_position, closer);
+                return;
+            }
+        }
+
+        self.cursor_position = self.document.dedent_for_closer(&self.cursor_position, c);
+        let len = self.document.row(y).map_or(0, Row::len);
+        self.document.insert(&self.cursor_position, c);
+
+        // A combining mark joins the grapheme before it rather than adding one
+        if self.document.row(y).map_or(0, Row::len) > len {
+            self.move_cursor(KeyCode::Right);
+        }
+    }
+
+    fn wrap_selection(&mut self, start: &Position, end: &Position, opener: char, closer: char) {
+        self.document.insert(end, closer);
+        self.document.insert(start, opener);
+
+        let shift = |position: &mut Position| {
+            if position.y == start.y {
+                position.x = position.x.saturating_add(1);
+            }
+        };
+        shift(&mut self.cursor_position);
+        if let Some(anchor) = &mut self.selection_anchor {
+            shift(anchor);
+        }
+    }
+
+    fn backspace(&mut self) {
+        if self.cursor_position.x == 0 && self.cursor_position.y == 0 {
+            return;
+        }
+
+        self.move_cursor(KeyCode::Left);
+
+        let Position { x, y } = self.cursor_position;
+        let is_empty_pair = self.config.editor.auto_pairs
+            && self.document.row(y).is_some_and(|row| {
+                let opener = row.grapheme(x).and_then(|opener| opener.chars().next());
+                let closer = row.grapheme(x.saturating_add(1));
+                opener
+                    .and_then(|opener| self.document.highlighting_options().closing_pair(opener))
+                    .is_some_and(|expected| closer == Some(expected.encode_utf8(&mut [0; 4])))
+            });
+
+        self.document.delete(&self.cursor_position);
+        if is_empty_pair {
+            self.document.delete(&self.cursor_position);
+        }
     }
 
     fn move_cursor(&mut self, key: KeyCode) {
         use KeyCode::{Down, End, Home, Left, PageDown, PageUp, Right, Up};
 
-        self.terminal.update_size();
-        let terminal_height = self.terminal.size().height as usize;
+        let terminal_height = self.text_height();
         let Position { mut x, mut y } = self.cursor_position;
         let height = self.document.len();
         let mut width = if let Some(row) = self.document.row(y) {
@@ -289,8 +1153,13 @@ impl Editor {
         } else {
             0
         };
+        // Moving between rows keeps the display column rather than the grapheme
+        let column = self.column(x, y);
 
         match key {
+            Up | Down if self.wrap_width().is_some() => {
+                (x, y) = self.move_visual_line(x, y, key == Up);
+            },
             Up => y = y.saturating_sub(1),
             Down => {
                 if y < height {
@@ -341,6 +1210,10 @@ impl Editor {
             _ => (),
         }
 
+        if matches!(key, Up | Down | PageUp | PageDown) && self.wrap_width().is_none() {
+            x = self.index_at_column(column, y);
+        }
+
         width = if let Some(row) = self.document.row(y) {
             row.len()
         } else {
@@ -353,11 +1226,189 @@ impl Editor {
         self.cursor_position = Position { x, y };
     }
 
+    fn gutter_width(&self) -> usize {
+        if self.config.editor.line_numbers == LineNumbers::Off {
+            return 0;
+        }
+
+        let digits = self.document.len().max(1).to_string().len();
+        digits.saturating_add(1)
+    }
+
+    fn column(&self, x: usize, y: usize) -> usize {
+        self.document
+            .row(y)
+            .map_or(0, |row| row.column(x, self.document.settings().tab_width))
+    }
+
+    fn index_at_column(&self, column: usize, y: usize) -> usize {
+        self.document.row(y).map_or(0, |row| {
+            row.index_at_column(column, self.document.settings().tab_width)
+        })
+    }
+
+    fn text_width(&self) -> usize {
+        self.area.width.saturating_sub(self.gutter_width())
+    }
+
+    fn text_height(&self) -> usize {
+        self.area.height.saturating_sub(1)
+    }
+
+    fn wrap_width(&self) -> Option<usize> {
+        self.config
+            .editor
+            .soft_wrap
+            .then(|| self.text_width().saturating_sub(1).max(1))
+    }
+
+    fn wrap_points(&self, y: usize) -> Vec<usize> {
+        match (self.wrap_width(), self.document.row(y)) {
+            (Some(width), Some(row)) => row.wrap_points(width, self.document.settings().tab_width),
+            _ => vec![0],
+        }
+    }
+
+    fn wrap_line(&self, x: usize, y: usize) -> usize {
+        self.wrap_points(y)
+            .iter()
+            .rposition(|start| *start <= x)
+            .unwrap_or_default()
+    }
+
+    /// Where the cursor at `x`, `y` ends up moving one screen line up or down, keeping its
+    /// column within the line.
+    fn move_visual_line(&self, x: usize, y: usize, up: bool) -> (usize, usize) {
+        let starts = self.wrap_points(y);
+        let line = self.wrap_line(x, y);
+        let start = starts.get(line).copied().unwrap_or_default();
+        let column = self.column(x, y).saturating_sub(self.column(start, y));
+
+        let (y, line) = if up {
+            match line.checked_sub(1) {
+                Some(line) => (y, line),
+                None if y > 0 => {
+                    let y = y.saturating_sub(1);
+                    (y, self.wrap_points(y).len().saturating_sub(1))
+                },
+                None => return (x, y),
+            }
+        } else if line.saturating_add(1) < starts.len() {
+            (y, line.saturating_add(1))
+        } else if y < self.document.len() {
+            (y.saturating_add(1), 0)
+        } else {
+            return (x, y);
+        };
+
+        // Stay before the start of the next line, which is drawn on that line
+        let starts = self.wrap_points(y);
+        let start = starts.get(line).copied().unwrap_or_default();
+        let x = self.index_at_column(self.column(start, y).saturating_add(column), y);
+        let x = match starts.get(line.saturating_add(1)) {
+            Some(next) => x.min(next.saturating_sub(1)),
+            None => x,
+        };
+
+        (x, y)
+    }
+
+    fn screen_lines(&self) -> Vec<ScreenLine> {
+        let height = self.text_height();
+        let mut lines = Vec::with_capacity(height);
+        let mut y = self.offset.y;
+
+        while lines.len() < height {
+            if self.wrap_width().is_none() || y >= self.document.len() {
+                lines.push(ScreenLine {
+                    y,
+                    start: self.offset.x,
+                    end: self.offset.x.saturating_add(self.text_width()),
+                    continued: false,
+                    wraps: false,
+                });
+            } else {
+                let starts = self.wrap_points(y);
+                let len = self.document.row(y).map_or(0, Row::len);
+                let width = self.column(len, y);
+                let skipped = if y == self.offset.y {
+                    self.offset_wrap
+                } else {
+                    0
+                };
+
+                for (line, start) in starts.iter().enumerate().skip(skipped) {
+                    let next = starts.get(line.saturating_add(1));
+                    lines.push(ScreenLine {
+                        y,
+                        start: self.column(*start, y),
+                        end: next.map_or(width, |next| self.column(*next, y)),
+                        continued: line > 0,
+                        wraps: next.is_some(),
+                    });
+                }
+            }
+            y = y.saturating_add(1);
+        }
+
+        lines.truncate(height);
+        lines
+    }
+
+    fn cursor_screen_position(&self) -> Option<Position> {
+        let Position { x, y } = self.cursor_position;
+        let column = self.column(x, y);
+        let lines = self.screen_lines();
+        let (screen_y, line) = lines.iter().enumerate().find(|(_, line)| {
+            line.y == y && line.start <= column && (column < line.end || !line.wraps)
+        })?;
+
+        Some(Position {
+            x: column
+                .saturating_sub(line.start)
+                .saturating_add(self.gutter_width())
+                .saturating_add(self.area.x),
+            y: screen_y.saturating_add(self.area.y),
+        })
+    }
+
+    /// The position of the text drawn at `column`, `row` of the screen within the focused
+    /// window. Past the end of a line it is the end of the line, and below the end of the
+    /// document it is on the last row.
+    fn position_at(&self, column: usize, row: usize) -> Position {
+        let lines = self.screen_lines();
+        let Some(line) = row
+            .checked_sub(self.area.y)
+            .and_then(|index| lines.get(index))
+        else {
+            return self.cursor_position.clone();
+        };
+
+        let y = line.y.min(self.document.len().saturating_sub(1));
+        let text_column = column
+            .saturating_sub(self.area.x)
+            .saturating_sub(self.gutter_width());
+        let x = self.index_at_column(line.start.saturating_add(text_column), y);
+        let x = if line.wraps {
+            x.min(self.index_at_column(line.end, y).saturating_sub(1))
+        } else {
+            x
+        };
+
+        Position { x, y }
+    }
+
     fn scroll(&mut self) {
+        if self.wrap_width().is_some() {
+            self.scroll_wrapped();
+            return;
+        }
+
         let Position { x, y } = self.cursor_position;
-        let width = self.terminal.size().width as usize;
-        let height = self.terminal.size().height as usize;
-        let mut offset = &mut self.offset;
+        let x = self.column(x, y);
+        let width = self.text_width();
+        let height = self.text_height();
+        let offset = &mut self.offset;
 
         if y < offset.y {
             offset.y = y;
@@ -372,62 +1423,177 @@ impl Editor {
         }
     }
 
+    fn scroll_wrapped(&mut self) {
+        let Position { x, y } = self.cursor_position;
+        let height = self.text_height();
+        let line = self.wrap_line(x, y);
+
+        self.offset.x = 0;
+        self.offset_wrap = self
+            .offset_wrap
+            .min(self.wrap_points(self.offset.y).len().saturating_sub(1));
+        if (y, line) < (self.offset.y, self.offset_wrap) {
+            self.offset.y = y;
+            self.offset_wrap = line;
+            return;
+        }
+
+        // The screen lines from the top of the screen down to the cursor
+        let lines = (self.offset.y..y)
+            .map(|y| self.wrap_points(y).len())
+            .sum::<usize>()
+            .saturating_add(line)
+            .saturating_add(1)
+            .saturating_sub(self.offset_wrap);
+
+        for _ in height..lines {
+            if self.offset_wrap.saturating_add(1) < self.wrap_points(self.offset.y).len() {
+                self.offset_wrap = self.offset_wrap.saturating_add(1);
+            } else {
+                self.offset.y = self.offset.y.saturating_add(1);
+                self.offset_wrap = 0;
+            }
+        }
+    }
+
     fn draw_welcome_message(&self) {
-        let mut welcome_message = format!("Hecto editor -- version {}\r", VERSION);
-        let width = self.terminal.size().width as usize;
-        let len = welcome_message.len();
+        let welcome_message = format!("Hecto editor -- version {}", VERSION);
+        let width = self.area.width;
         #[allow(clippy::integer_arithmetic, clippy::integer_division)]
-        let padding = width.saturating_sub(len) / 2;
-        let spaces = " ".repeat(padding.saturating_sub(1));
-        welcome_message = format!("~{}{}", spaces, welcome_message);
-        welcome_message.truncate(width);
-        println!("{}\r", welcome_message);
+        let padding = width.saturating_sub(welcome_message.len()) / 2;
+        let mut line = format!(
+            "{}{}",
+            " ".repeat(padding.saturating_sub(1)),
+            welcome_message
+        );
+        line.truncate(width.saturating_sub(1));
+        line.push_str(&" ".repeat(width.saturating_sub(line.len()).saturating_sub(1)));
+        print!(
+            "{}{}",
+            self.config.theme.ui(UiElement::Tildes).apply('~'),
+            line
+        );
     }
 
-    fn draw_row(&self, row: &Row) {
-        let width = self.terminal.size().width as usize;
-        let start = self.offset.x;
-        let end = self.offset.x.saturating_add(width);
-        let row = row.render(start, end);
-        println!("{}\r", row);
+    fn draw_row(&self, row: &Row, line: &ScreenLine, brackets: &[usize], selection: &Range<usize>) {
+        let tab_width = self.document.settings().tab_width;
+        // Whitespace hanging off the end of a wrapped line makes way for the indicator
+        let width = if line.wraps {
+            self.text_width().saturating_sub(1)
+        } else {
+            self.text_width()
+        };
+        let end = line.end.min(line.start.saturating_add(width));
+        let mut rendered = row.render(
+            line.start..end,
+            tab_width,
+            &self.config.theme,
+            brackets,
+            selection,
+        );
+
+        // Padding over whatever was drawn there before, as the line is not cleared
+        let drawn = end
+            .min(row.column(row.len(), tab_width))
+            .saturating_sub(line.start);
+        rendered.push_str(&" ".repeat(width.saturating_sub(drawn)));
+        if line.wraps {
+            rendered.push_str(
+                &self
+                    .config
+                    .theme
+                    .ui(UiElement::WrapIndicator)
+                    .apply(WRAP_INDICATOR)
+                    .to_string(),
+            );
+        }
+
+        print!("{}", rendered);
+    }
+
+    fn draw_line_number(&self, line: &ScreenLine) {
+        let width = self.gutter_width();
+        if width == 0 {
+            return;
+        }
+        if line.continued {
+            print!("{}", " ".repeat(width));
+            return;
+        }
+
+        let y = line.y;
+        let current = self.cursor_position.y;
+        let number = if self.config.editor.line_numbers == LineNumbers::Relative && y != current {
+            y.abs_diff(current)
+        } else {
+            y.saturating_add(1)
+        };
+        let element = if y == current {
+            UiElement::CurrentLineNumber
+        } else {
+            UiElement::LineNumbers
+        };
+
+        let number = format!("{:>1$} ", number, width.saturating_sub(1));
+        print!("{}", self.config.theme.ui(element).apply(number));
     }
 
     #[allow(clippy::integer_arithmetic, clippy::integer_division)]
     fn draw_rows(&self) {
-        let height = self.terminal.size().height;
-
-        for terminal_row in 0..height {
-            Terminal::clear_current_line();
+        let height = self.text_height();
+        let visible = self.offset.y..self.offset.y.saturating_add(height);
+        let bracket_pair = self
+            .document
+            .matching_bracket(&self.cursor_position, visible);
+        let selection = self.selection();
+
+        for (terminal_row, line) in self.screen_lines().iter().enumerate() {
+            Terminal::cursor_position(&Position {
+                x: self.area.x,
+                y: self.area.y + terminal_row,
+            });
 
-            if let Some(row) = self
-                .document
-                .row(self.offset.y.saturating_add(terminal_row as usize))
-            {
-                self.draw_row(row);
+            let y = line.y;
+            if let Some(row) = self.document.row(y) {
+                let brackets: Vec<usize> = bracket_pair
+                    .iter()
+                    .flat_map(|(bracket, found)| [bracket, found])
+                    .filter(|position| position.y == y)
+                    .map(|position| position.x)
+                    .collect();
+                let selected = match &selection {
+                    Some((start, end)) if (start.y..=end.y).contains(&y) => {
+                        let from = if y == start.y { start.x } else { 0 };
+                        let to = if y == end.y { end.x } else { row.len() };
+                        from..to
+                    },
+                    _ => 0..0,
+                };
+                self.draw_line_number(line);
+                self.draw_row(row, line, &brackets, &selected);
             } else if self.document.is_empty() && terminal_row == height / 3 {
                 self.draw_welcome_message();
-            } else {
-                println!("~\r");
+            } else if self.area.width > 0 {
+                print!(
+                    "{}{}",
+                    self.config.theme.ui(UiElement::Tildes).apply('~'),
+                    " ".repeat(self.area.width - 1)
+                );
             }
         }
     }
 
-    fn draw_status_bar(&self) {
-        let width = self.terminal.size().width as usize;
+    fn draw_status_bar(&self, focused: bool) {
+        let Some(y) = self.area.y.saturating_add(self.area.height).checked_sub(1) else {
+            return;
+        };
+        let width = self.area.width;
         let mut file_name = "[No Name]".to_owned();
 
-        let modified_indicator = if self.document.is_dirty() { " [+]" } else { "" };
-
-        if let Some(name) = &self.document.file_name {
-            file_name = name.clone();
-            file_name.truncate(width.saturating_sub(30));
-        }
-
-        let mut status = format!(
-            "{} - {} lines{}",
-            file_name,
+        let details = format!(
+            " - {} lines{}",
             self.document.len(),
-            modified_indicator
+            modified_indicator(&self.document)
         );
         let line_indicator = format!(
             "{} | {}/{}",
@@ -436,26 +1602,151 @@ impl Editor {
             self.document.len()
         );
 
+        if let Some(name) = &self.document.file_name {
+            // The file name gives way to the rest in narrow windows
+            let room = width
+                .saturating_sub(details.width())
+                .saturating_sub(line_indicator.width())
+                .saturating_sub(1);
+            file_name = truncate_to_width(name, room);
+        }
+
+        let mut status = format!("{}{}", file_name, details);
+
         #[allow(clippy::integer_arithmetic)]
-        let len = status.len() + line_indicator.len();
+        let len = status.width() + line_indicator.width();
 
         status.push_str(&" ".repeat(width.saturating_sub(len)));
-        status = format!("{}{}", status, line_indicator);
-        status.truncate(width);
+        status = truncate_to_width(&format!("{}{}", status, line_indicator), width);
+
+        let element = if focused {
+            UiElement::StatusBar
+        } else {
+            UiElement::InactiveStatusBar
+        };
+        Terminal::cursor_position(&Position { x: self.area.x, y });
+        print!("{}", self.config.theme.ui(element).apply(status));
+    }
+
+    fn draw_separators(&self, separators: &[Area]) {
+        let style = self.config.theme.ui(UiElement::WindowSeparator);
+
+        for area in separators.iter().filter(|area| area.width > 0) {
+            for y in area.y..area.y.saturating_add(area.height) {
+                Terminal::cursor_position(&Position { x: area.x, y });
+                print!("{}", style.apply(WINDOW_SEPARATOR));
+            }
+        }
+    }
+
+    fn tab_title(&self, buffer: usize) -> String {
+        let Some(document) = self.buffer(buffer) else {
+            return String::new();
+        };
+        let name = document.file_name.as_deref().map_or("[No Name]", base_name);
 
-        Terminal::set_bg_color(STATUS_BG_COLOR);
-        Terminal::set_fg_color(STATUS_FG_COLOR);
-        println!("{}\r", status);
-        Terminal::reset_color();
+        format!(" {}{} ", name, modified_indicator(document))
+    }
+
+    /// The tabs that fit on the tab bar from `tab_offset` on, as the document and the columns
+    /// its tab takes up. The first one is shown even if it is too wide.
+    fn tab_columns(&self) -> Vec<(usize, Range<usize>)> {
+        let width = self.terminal.size().width as usize;
+        let mut tabs = Vec::new();
+        // The first column is taken by the marker of tabs scrolled past
+        let mut column = usize::from(self.tab_offset > 0);
+
+        for buffer in self.tab_offset..self.buffers.len() {
+            let end = column.saturating_add(self.tab_title(buffer).width());
+            // The last column is kept for the marker of tabs that do not fit
+            let room = if buffer.saturating_add(1) == self.buffers.len() {
+                width
+            } else {
+                width.saturating_sub(1)
+            };
+            if end > room && !tabs.is_empty() {
+                break;
+            }
+
+            tabs.push((buffer, column..end.min(width)));
+            column = end;
+        }
+
+        tabs
+    }
+
+    fn scroll_tabs(&mut self) {
+        self.tab_offset = self.tab_offset.min(self.buffer);
+
+        while self.tab_offset < self.buffer
+            && !self
+                .tab_columns()
+                .iter()
+                .any(|(buffer, _)| *buffer == self.buffer)
+        {
+            self.tab_offset = self.tab_offset.saturating_add(1);
+        }
+    }
+
+    fn draw_tab_bar(&self) {
+        let width = self.terminal.size().width as usize;
+        let style = self.config.theme.ui(UiElement::TabBar);
+        let tabs = self.tab_columns();
+        let mut line = String::new();
+        let mut column = 0;
+
+        if self.tab_offset > 0 {
+            line.push_str(&style.apply('<').to_string());
+            column = 1;
+        }
+        for (buffer, columns) in &tabs {
+            let element = if *buffer == self.buffer {
+                UiElement::ActiveTab
+            } else {
+                UiElement::TabBar
+            };
+            let title = truncate_to_width(&self.tab_title(*buffer), columns.len());
+            line.push_str(&self.config.theme.ui(element).apply(title).to_string());
+            column = columns.end;
+        }
+
+        let hidden = tabs
+            .last()
+            .is_some_and(|(buffer, _)| buffer.saturating_add(1) < self.buffers.len());
+        let padding = width
+            .saturating_sub(column)
+            .saturating_sub(usize::from(hidden));
+        line.push_str(&style.apply(" ".repeat(padding)).to_string());
+        if hidden {
+            line.push_str(&style.apply('>').to_string());
+        }
+
+        Terminal::cursor_position(&Position::default());
+        print!("{}", line);
+    }
+
+    fn draw_too_small(&self) {
+        let mut message = "Terminal too small".to_owned();
+        message.truncate(self.terminal.size().width as usize);
+
+        Terminal::clear_screen();
+        Terminal::cursor_position(&Position::default());
+        print!("{}", message);
     }
 
     fn draw_message_bar(&self) {
+        Terminal::cursor_position(&Position {
+            x: 0,
+            y: self.terminal.size().height as usize,
+        });
         Terminal::clear_current_line();
         let message = &self.status_message;
-        if Instant::now() - message.time < Duration::new(5, 0) {
-            let mut text = message.text.clone();
-            text.truncate(self.terminal.size().width as usize);
-            print!("{}", text);
+        if Instant::now() - message.time < self.config.editor.message_timeout() {
+            let text = truncate_to_width(&message.text, self.terminal.size().width as usize);
+            print!(
+                "{}",
+                self.config.theme.ui(UiElement::MessageBar).apply(text)
+            );
         }
     }
 
@@ -469,7 +1760,14 @@ impl Editor {
             self.status_message = StatusMessage::from(format!("{}{}", prompt, result));
             self.refresh_screen()?;
 
-            let key = Terminal::read_key()?.code;
+            let key = match Terminal::read_event()? {
+                Event::Key(key) => key.code,
+                Event::Resize(..) => {
+                    self.resize();
+                    continue;
+                },
+                Event::Mouse(_) => continue,
+            };
             match key {
                 KeyCode::Backspace => {
                     if !result.is_empty() {
@@ -505,14 +1803,20 @@ impl Editor {
         #[allow(clippy::or_fun_call)]
         let file_name = self.prompt("Open file: ", |_, _, _| {}).unwrap_or(None);
         if let Some(file_name) = file_name {
-            let doc = Document::open(&file_name);
-            #[allow(clippy::unwrap_used)]
-            if let Ok(doc) = doc.await {
-                // Get the actual file name
-                self.rpc
-                    .file_name(file_name.split('/').last().unwrap().to_owned());
+            // A document that is already open is shown rather than opened twice
+            let open = (0..self.buffers.len()).find(|buffer| {
+                self.buffer(*buffer)
+                    .is_some_and(|document| document.file_name.as_ref() == Some(&file_name))
+            });
+            if let Some(buffer) = open {
+                self.show_buffer(buffer);
+                return Ok(());
+            }
 
-                self.document = doc;
+            let doc = Document::open(&file_name, &self.config.languages);
+            if let Ok(doc) = doc.await {
+                self.buffers.push(doc);
+                self.show_buffer(self.buffers.len().saturating_sub(1));
             } else {
                 self.status_message =
                     StatusMessage::from(format!("ERR: Could not open file: {}", file_name));
@@ -523,6 +1827,94 @@ impl Editor {
     }
 }
 
+fn modified_indicator(document: &Document) -> &'static str {
+    if document.is_dirty() {
+        " [+]"
+    } else {
+        ""
+    }
+}
+
+fn base_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+fn truncate_to_width(text: &str, width: usize) -> String {
+    let mut used = 0_usize;
+
+    text.chars()
+        .take_while(|c| {
+            used = used.saturating_add(c.width().unwrap_or(0));
+            used <= width
+        })
+        .collect()
+}
+
+fn help_message(keymap: &Keymap) -> String {
+    let help: Vec<String> = [
+        (Action::Find, "Find"),
+        (Action::Save, "Save"),
+        (Action::Quit, "Quit"),
+        (Action::Open, "Open"),
+    ]
+    .into_iter()
+    .filter_map(|(action, label)| {
+        let keys = keymap.keys(action);
+        (!keys.is_empty()).then(|| format!("{} = {}", keys.join("/"), label))
+    })
+    .collect();
+
+    format!("HELP: {}", help.join(" | "))
+}
+
+/// Whether typing `opener` at grapheme `at` of `row` should insert `closer` as well: not within
+/// strings and comments, not in front of a word, and for quotes not right after a word either.
+fn should_pair(row: &Row, at: usize, opener: char, closer: char) -> bool {
+    if row.is_in_literal(at) || is_word(row.grapheme(at)) {
+        return false;
+    }
+
+    opener != closer
+        || at
+            .checked_sub(1)
+            .is_none_or(|before| !row.is_literal_at(before) && !is_word(row.grapheme(before)))
+}
+
+fn is_word(grapheme: Option<&str>) -> bool {
+    grapheme
+        .and_then(|grapheme| grapheme.chars().next())
+        .is_some_and(|c| c.is_alphanumeric() || c == '_')
+}
+
+fn parse_args() -> (Option<PathBuf>, Option<String>) {
+    let mut config_path = None;
+    let mut file_path = None;
+    let mut args = env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        if arg == "--config" || arg == "-c" {
+            config_path = args.next().map(PathBuf::from);
+        } else if let Some(path) = arg.strip_prefix("--config=") {
+            config_path = Some(PathBuf::from(path));
+        } else if file_path.is_none() {
+            file_path = Some(arg);
+        }
+    }
+
+    (config_path, file_path)
+}
+
+fn config_error_message(errors: &[String]) -> Option<String> {
+    let first = errors.first()?;
+    let more = if errors.len() > 1 {
+        format!(" (+{} more)", errors.len().saturating_sub(1))
+    } else {
+        String::new()
+    };
+
+    Some(format!("ERR: config: {}{}", first, more))
+}
+
 #[allow(clippy::panic)]
 fn die(e: &std::io::Error) {
     Terminal::clear_screen();
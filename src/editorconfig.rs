@@ -0,0 +1,512 @@
+use std::path::{Component, Path, PathBuf};
+
+use tokio::fs;
+
+const FILE_NAME: &str = ".editorconfig";
+const MAX_RANGE_EXPANSION: i64 = 1024;
+
+#[derive(PartialEq, Clone, Copy)]
+pub enum IndentStyle {
+    Spaces,
+    Tabs,
+}
+
+#[derive(PartialEq, Clone, Copy)]
+pub enum EndOfLine {
+    Lf,
+    CrLf,
+    Cr,
+}
+
+impl EndOfLine {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EndOfLine::Lf => "\n",
+            EndOfLine::CrLf => "\r\n",
+            EndOfLine::Cr => "\r",
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Copy)]
+pub enum Charset {
+    Utf8,
+    Utf8Bom,
+    Latin1,
+}
+
+impl Charset {
+    const BOM: &'static str = "\u{feff}";
+
+    pub fn decode(self, bytes: &[u8]) -> Option<String> {
+        match self {
+            Charset::Latin1 => Some(bytes.iter().map(|byte| char::from(*byte)).collect()),
+            Charset::Utf8 | Charset::Utf8Bom => {
+                let contents = String::from_utf8(bytes.to_vec()).ok()?;
+
+                Some(match contents.strip_prefix(Self::BOM) {
+                    Some(stripped) => stripped.to_owned(),
+                    None => contents,
+                })
+            },
+        }
+    }
+
+    /// Encodes `contents`, failing with the first character the charset has no byte for.
+    pub fn encode(self, contents: &str) -> Result<Vec<u8>, char> {
+        match self {
+            Charset::Utf8 => Ok(contents.as_bytes().to_vec()),
+            Charset::Utf8Bom => Ok(format!("{}{}", Self::BOM, contents).into_bytes()),
+            Charset::Latin1 => contents
+                .chars()
+                .map(|c| u8::try_from(c).map_err(|_| c))
+                .collect(),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Charset::Utf8 => "UTF-8",
+            Charset::Utf8Bom => "UTF-8 with BOM",
+            Charset::Latin1 => "Latin-1",
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct EditorSettings {
+    pub indent_style: IndentStyle,
+    pub indent_size: usize,
+    pub tab_width: usize,
+    pub end_of_line: EndOfLine,
+    pub charset: Charset,
+    pub trim_trailing_whitespace: bool,
+    pub insert_final_newline: bool,
+    pub max_line_length: Option<usize>,
+}
+
+impl Default for EditorSettings {
+    fn default() -> Self {
+        Self {
+            indent_style: IndentStyle::Spaces,
+            indent_size: 4,
+            tab_width: 4,
+            end_of_line: EndOfLine::Lf,
+            charset: Charset::Utf8,
+            trim_trailing_whitespace: false,
+            insert_final_newline: true,
+            max_line_length: None,
+        }
+    }
+}
+
+impl EditorSettings {
+    /// Applies every `.editorconfig` section matching `file_name`, walking up the directory
+    /// tree until a file declaring `root = true` is found.
+    pub async fn resolve(file_name: &str) -> Self {
+        let path = match std::env::current_dir() {
+            Ok(dir) => dir.join(file_name),
+            Err(_) => PathBuf::from(file_name),
+        };
+        // Files that do not exist yet cannot be canonicalized
+        let path = match fs::canonicalize(&path).await {
+            Ok(canonical) => canonical,
+            Err(_) => normalize(&path),
+        };
+
+        let mut files = Vec::new();
+        for dir in path.ancestors().skip(1) {
+            if let Ok(contents) = fs::read_to_string(dir.join(FILE_NAME)).await {
+                let file = parse(&contents);
+                let is_root = file.is_root;
+                files.push((dir.to_owned(), file));
+
+                if is_root {
+                    break;
+                }
+            }
+        }
+
+        Self::from_files(&path, &files)
+    }
+
+    /// Applies the sections matching `path` of `files`, found in its directory and the ones
+    /// above it, closest first.
+    fn from_files(path: &Path, files: &[(PathBuf, EditorConfigFile)]) -> Self {
+        let mut settings = Self::default();
+        let mut indent_size_is_tab = false;
+        let mut tab_width_set = false;
+
+        // Closer files take precedence, so they are applied last
+        for (dir, file) in files.iter().rev() {
+            let Ok(relative) = path.strip_prefix(dir) else {
+                continue;
+            };
+
+            for section in &file.sections {
+                if !section_matches(&section.glob, relative) {
+                    continue;
+                }
+
+                for (key, value) in &section.properties {
+                    match key.as_str() {
+                        "indent_size" if value == "tab" => indent_size_is_tab = true,
+                        "tab_width" => {
+                            if let Ok(width) = value.parse() {
+                                settings.tab_width = width;
+                                tab_width_set = true;
+                            }
+                        },
+                        "indent_size" => {
+                            if let Ok(size) = value.parse() {
+                                settings.indent_size = size;
+                                indent_size_is_tab = false;
+                            }
+                        },
+                        _ => settings.set(key, value),
+                    }
+                }
+            }
+        }
+
+        if indent_size_is_tab {
+            settings.indent_size = settings.tab_width;
+        } else if !tab_width_set {
+            settings.tab_width = settings.indent_size;
+        }
+
+        settings
+    }
+
+    fn set(&mut self, key: &str, value: &str) {
+        match (key, value) {
+            ("indent_style", "space") => self.indent_style = IndentStyle::Spaces,
+            ("indent_style", "tab") => self.indent_style = IndentStyle::Tabs,
+            ("end_of_line", "lf") => self.end_of_line = EndOfLine::Lf,
+            ("end_of_line", "crlf") => self.end_of_line = EndOfLine::CrLf,
+            ("end_of_line", "cr") => self.end_of_line = EndOfLine::Cr,
+            ("charset", "utf-8") => self.charset = Charset::Utf8,
+            ("charset", "utf-8-bom") => self.charset = Charset::Utf8Bom,
+            ("charset", "latin1") => self.charset = Charset::Latin1,
+            ("trim_trailing_whitespace", value) => {
+                self.trim_trailing_whitespace = value == "true";
+            },
+            ("insert_final_newline", value) => self.insert_final_newline = value == "true",
+            // `off` turns a limit set for a parent directory off again
+            ("max_line_length", value) => self.max_line_length = value.parse().ok(),
+            _ => (),
+        }
+    }
+
+    /// The text inserted by a single press of Tab at display column `column`.
+    pub fn indent_unit(&self, column: usize) -> String {
+        match self.indent_style {
+            IndentStyle::Tabs => "\t".to_owned(),
+            IndentStyle::Spaces => {
+                let size = self.indent_size.max(1);
+
+                #[allow(clippy::integer_arithmetic)]
+                " ".repeat(size - column % size)
+            },
+        }
+    }
+}
+
+struct Section {
+    glob: String,
+    properties: Vec<(String, String)>,
+}
+
+struct EditorConfigFile {
+    is_root: bool,
+    sections: Vec<Section>,
+}
+
+fn parse(contents: &str) -> EditorConfigFile {
+    let mut file = EditorConfigFile {
+        is_root: false,
+        sections: Vec::new(),
+    };
+
+    for line in contents.lines() {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+
+        if let Some(glob) = line
+            .strip_prefix('[')
+            .and_then(|line| line.strip_suffix(']'))
+        {
+            file.sections.push(Section {
+                glob: glob.to_owned(),
+                properties: Vec::new(),
+            });
+        } else if let Some((key, value)) = line.split_once('=') {
+            let key = key.trim().to_lowercase();
+            let value = value.trim().to_lowercase();
+
+            if let Some(section) = file.sections.last_mut() {
+                section.properties.push((key, value));
+            } else if key == "root" {
+                file.is_root = value == "true";
+            }
+        }
+    }
+
+    file
+}
+
+/// Resolves `.` and `..` in `path` without touching the file system.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => {
+                normalized.pop();
+            },
+            component => normalized.push(component),
+        }
+    }
+
+    normalized
+}
+
+fn section_matches(glob: &str, relative: &Path) -> bool {
+    let path = relative.to_string_lossy().replace('\\', "/");
+
+    // Globs without a slash match the file name in any subdirectory
+    let glob = if let Some(glob) = glob.strip_prefix('/') {
+        glob.to_owned()
+    } else if glob.contains('/') {
+        glob.to_owned()
+    } else {
+        format!("**/{}", glob)
+    };
+
+    expand_braces(&glob).iter().any(|glob| {
+        let glob: Vec<char> = glob.chars().collect();
+        let path: Vec<char> = path.chars().collect();
+        glob_matches(&glob, &path)
+    })
+}
+
+/// Expands `{a,b}` alternatives and `{1..3}` numeric ranges into plain globs.
+fn expand_braces(glob: &str) -> Vec<String> {
+    let Some(open) = glob.find('{') else {
+        return vec![glob.to_owned()];
+    };
+
+    let mut depth = 0;
+    let mut close = None;
+    for (index, c) in glob.char_indices().skip_while(|(index, _)| *index < open) {
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    close = Some(index);
+                    break;
+                }
+            },
+            _ => (),
+        }
+    }
+
+    let Some(close) = close else {
+        return vec![glob.to_owned()];
+    };
+
+    #[allow(
+        clippy::string_slice,
+        clippy::indexing_slicing,
+        clippy::integer_arithmetic
+    )]
+    let (prefix, inner, suffix) = (&glob[..open], &glob[open + 1..close], &glob[close + 1..]);
+
+    let mut alternatives = Vec::new();
+    if let Some((start, end)) = inner.split_once("..") {
+        if let (Ok(start), Ok(end)) = (start.parse::<i64>(), end.parse::<i64>()) {
+            let (start, end) = (start.min(end), start.max(end));
+            if end.saturating_sub(start) <= MAX_RANGE_EXPANSION {
+                alternatives.extend((start..=end).map(|number| number.to_string()));
+            }
+        }
+    } else {
+        let mut depth = 0;
+        let mut current = String::new();
+        for c in inner.chars() {
+            match c {
+                '{' => depth += 1,
+                '}' => depth -= 1,
+                ',' if depth == 0 => {
+                    alternatives.push(current.clone());
+                    current.clear();
+                    continue;
+                },
+                _ => (),
+            }
+            current.push(c);
+        }
+        alternatives.push(current);
+    }
+
+    // A single alternative is not a brace expansion: `{a}` matches literally
+    if alternatives.len() < 2 && inner.split_once("..").is_none() {
+        return expand_braces(suffix)
+            .into_iter()
+            .map(|suffix| format!("{}{{{}}}{}", prefix, inner, suffix))
+            .collect();
+    }
+
+    let mut globs = Vec::new();
+    for alternative in alternatives {
+        globs.extend(expand_braces(&format!(
+            "{}{}{}",
+            prefix, alternative, suffix
+        )));
+    }
+    globs
+}
+
+#[allow(clippy::indexing_slicing, clippy::integer_arithmetic)]
+fn glob_matches(glob: &[char], path: &[char]) -> bool {
+    match glob.first() {
+        None => path.is_empty(),
+        Some('*') if glob.get(1) == Some(&'*') => {
+            // `**/` also matches zero directories
+            if glob.get(2) == Some(&'/') && glob_matches(&glob[3..], path) {
+                return true;
+            }
+            (0..=path.len()).any(|skip| glob_matches(&glob[2..], &path[skip..]))
+        },
+        Some('*') => (0..=path.len())
+            .take_while(|skip| *skip == 0 || path[*skip - 1] != '/')
+            .any(|skip| glob_matches(&glob[1..], &path[skip..])),
+        Some('?') => match path.first() {
+            Some(c) if *c != '/' => glob_matches(&glob[1..], &path[1..]),
+            _ => false,
+        },
+        Some('[') => {
+            let close = match glob.iter().skip(1).position(|c| *c == ']') {
+                Some(close) => close + 1,
+                None => return path.first() == Some(&'[') && glob_matches(&glob[1..], &path[1..]),
+            };
+            let (negated, class) = match glob[1..close].split_first() {
+                Some(('!', class)) => (true, class),
+                _ => (false, &glob[1..close]),
+            };
+
+            match path.first() {
+                Some(c) if *c != '/' && class_contains(class, *c) != negated => {
+                    glob_matches(&glob[close + 1..], &path[1..])
+                },
+                _ => false,
+            }
+        },
+        Some('\\') if glob.len() > 1 => {
+            path.first() == Some(&glob[1]) && glob_matches(&glob[2..], &path[1..])
+        },
+        Some(c) => path.first() == Some(c) && glob_matches(&glob[1..], &path[1..]),
+    }
+}
+
+fn class_contains(class: &[char], c: char) -> bool {
+    let mut index = 0;
+
+    while let Some(start) = class.get(index) {
+        #[allow(clippy::integer_arithmetic)]
+        if let (Some('-'), Some(end)) = (class.get(index + 1), class.get(index + 2)) {
+            if (*start..=*end).contains(&c) {
+                return true;
+            }
+            index += 3;
+        } else {
+            if *start == c {
+                return true;
+            }
+            index += 1;
+        }
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn latin1_rejects_what_it_cannot_encode() {
+        assert_eq!(Charset::Latin1.encode("café"), Ok(b"caf\xe9".to_vec()));
+        assert_eq!(Charset::Latin1.encode("a → b"), Err('→'));
+    }
+
+    #[test]
+    fn globs_match_like_editorconfig() {
+        for (glob, path, expected) in [
+            ("*.rs", "main.rs", true),
+            ("*.rs", "src/main.rs", true),
+            ("src/*.rs", "src/main.rs", true),
+            ("src/*.rs", "src/bin/main.rs", false),
+            ("src/**.rs", "src/bin/main.rs", true),
+            ("src/**/main.rs", "src/main.rs", true),
+            ("src/**/main.rs", "src/a/b/main.rs", true),
+            ("src/**/main.rs", "srcmain.rs", false),
+            ("{a,b}.rs", "a.rs", true),
+            ("{a,b}.rs", "b.rs", true),
+            ("{a,b}.rs", "c.rs", false),
+            ("f{1..3}.txt", "f2.txt", true),
+            ("f{1..3}.txt", "f4.txt", false),
+            ("{a}.rs", "{a}.rs", true),
+            ("{a}.rs", "a.rs", false),
+            ("[!x].rs", "a.rs", true),
+            ("[!x].rs", "x.rs", false),
+            ("[a-c].rs", "b.rs", true),
+            ("[a-c].rs", "d.rs", false),
+            ("?.rs", "a.rs", true),
+            ("?.rs", "ab.rs", false),
+            ("/a.rs", "a.rs", true),
+            ("/a.rs", "sub/a.rs", false),
+            ("a.rs", "sub/a.rs", true),
+        ] {
+            assert_eq!(
+                section_matches(glob, Path::new(path)),
+                expected,
+                "{} against {}",
+                glob,
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn closer_files_take_precedence() {
+        let outer = parse("root = true\n[*]\nindent_style = tab\nindent_size = 2\n");
+        let inner = parse("[*.rs]\nindent_size = 8\n[*.md]\nindent_size = 3\n");
+        let files = vec![
+            (PathBuf::from("/project/sub"), inner),
+            (PathBuf::from("/project"), outer),
+        ];
+
+        let settings = EditorSettings::from_files(Path::new("/project/sub/main.rs"), &files);
+        assert!(settings.indent_style == IndentStyle::Tabs);
+        assert_eq!(settings.indent_size, 8);
+        assert_eq!(settings.tab_width, 8);
+
+        let settings = EditorSettings::from_files(Path::new("/project/main.rs"), &files);
+        assert_eq!(settings.indent_size, 2);
+    }
+
+    #[test]
+    fn paths_are_normalized() {
+        assert_eq!(
+            normalize(Path::new("/project/sub/../src/./main.rs")),
+            Path::new("/project/src/main.rs")
+        );
+        assert_eq!(normalize(Path::new("/../a")), Path::new("/a"));
+    }
+}
@@ -1,23 +1,286 @@
+use std::cmp;
+use std::collections::HashMap;
+use std::path::Path;
+
+use serde::Deserialize;
+use tokio::fs;
+
+use crate::config::FileTypeRule;
+
+const BUILTIN_LANGUAGES: [&str; 17] = [
+    include_str!("../languages/rust.toml"),
+    include_str!("../languages/c.toml"),
+    include_str!("../languages/cpp.toml"),
+    include_str!("../languages/go.toml"),
+    include_str!("../languages/python.toml"),
+    include_str!("../languages/typescript.toml"),
+    include_str!("../languages/shell.toml"),
+    include_str!("../languages/sql.toml"),
+    include_str!("../languages/json.toml"),
+    include_str!("../languages/yaml.toml"),
+    include_str!("../languages/toml.toml"),
+    include_str!("../languages/markdown.toml"),
+    include_str!("../languages/pascal.toml"),
+    include_str!("../languages/make.toml"),
+    include_str!("../languages/dockerfile.toml"),
+    include_str!("../languages/lua.toml"),
+    include_str!("../languages/haskell.toml"),
+];
+
+/// How many lines at the start and at the end of a file are searched for modelines.
+pub const MODELINE_LINES: usize = 5;
+
+const DEFAULT_AUTO_PAIRS: [&str; 4] = ["()", "[]", "{}", "\"\""];
+
+#[derive(Deserialize, Clone)]
 pub struct FileType {
     name: String,
+    #[serde(default)]
+    extensions: Vec<String>,
+    #[serde(default)]
+    filenames: Vec<String>,
+    #[serde(default)]
+    shebangs: Vec<String>,
+    /// Prefixes of the first non-blank line identifying the language, for files that nothing
+    /// else matched.
+    #[serde(default)]
+    first_lines: Vec<String>,
+    #[serde(flatten)]
     hl_opts: HighlightingOptions,
 }
 
-#[derive(Default)]
+#[derive(Deserialize, Default, Clone)]
+#[serde(default)]
 pub struct HighlightingOptions {
-    numbers: bool,
-    strings: bool,
+    numbers: Numbers,
+    strings: Vec<StringDelimiter>,
     chars: bool,
-    comments: bool,
-    multiline_comments: bool,
-    primary_keywords: Vec<String>,
-    secondary_keywords: Vec<String>,
+    line_comments: Vec<String>,
+    block_comments: Vec<BlockComment>,
+    keywords: Keywords,
+    functions: bool,
+    types: bool,
+    /// Marks an identifier as a macro when directly following it, like `!` in Rust.
+    macro_suffix: String,
+    /// Prefixes of attributes and decorators. One ending in `[` runs up to the matching `]`,
+    /// others cover the name following them.
+    attributes: Vec<String>,
+    lifetimes: bool,
+    operators: String,
+    punctuation: String,
+    indent: IndentRules,
+    paragraphs: ParagraphRules,
+    /// Two-character strings of an opening and a closing character typed together. Brackets and
+    /// double quotes when not given.
+    auto_pairs: Option<Vec<String>>,
+}
+
+/// When to indent or dedent automatically, on top of keeping the indentation of the row above.
+#[derive(Deserialize, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct IndentRules {
+    /// A row ending in one of these, outside of comments and strings, indents the next one.
+    pub openers: Vec<String>,
+    /// Typing one of these characters at the start of a row dedents it.
+    pub closers: String,
+}
+
+/// How text is laid out in paragraphs when reflowing, on top of comments.
+#[derive(Deserialize, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ParagraphRules {
+    /// Prefixes repeated on every row of a paragraph, like `>` for quotes in Markdown.
+    pub quotes: Vec<String>,
+    /// Markers starting a list item, which begins a paragraph of its own with the rows after
+    /// it indented past the marker.
+    pub list_markers: Vec<String>,
+    /// Whether a number followed by `.` or `)` starts a list item too.
+    pub numbered_lists: bool,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct BlockComment {
+    pub start: String,
+    pub end: String,
+    #[serde(default)]
+    pub nested: bool,
+}
+
+/// A string literal syntax. A bare string in a language file is shorthand for a delimiter that
+/// both opens and closes the string, with `\` as the escape character.
+#[derive(Deserialize, Clone)]
+#[serde(from = "RawStringDelimiter")]
+pub struct StringDelimiter {
+    pub start: String,
+    pub end: String,
+    pub escape: String,
+    pub multiline: bool,
+    /// Rust-style raw strings: `start`, any number of `#`, then `end` opens the string, which is
+    /// closed by `end` followed by the same number of `#`.
+    pub raw: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawStringDelimiter {
+    Delimiter(String),
+    Full {
+        start: String,
+        end: Option<String>,
+        escape: Option<String>,
+        #[serde(default)]
+        multiline: bool,
+        #[serde(default)]
+        raw: bool,
+    },
+}
+
+impl From<RawStringDelimiter> for StringDelimiter {
+    fn from(raw: RawStringDelimiter) -> Self {
+        match raw {
+            RawStringDelimiter::Delimiter(delimiter) => Self {
+                start: delimiter.clone(),
+                end: delimiter,
+                escape: "\\".to_owned(),
+                multiline: false,
+                raw: false,
+            },
+            RawStringDelimiter::Full {
+                start,
+                end,
+                escape,
+                multiline,
+                raw,
+            } => Self {
+                end: end.unwrap_or_else(|| start.clone()),
+                start,
+                escape: escape.unwrap_or_else(|| if raw { String::new() } else { "\\".to_owned() }),
+                multiline,
+                raw,
+            },
+        }
+    }
+}
+
+/// How number literals are lexed. `numbers = true` in a language file stands for decimal
+/// numbers with fractions and exponents, which is also what a `[numbers]` table starts from.
+#[derive(Deserialize, Clone)]
+#[serde(try_from = "RawNumbers")]
+pub struct Numbers {
+    pub enabled: bool,
+    /// Prefixes like `0x` with the radix of the digits following them, longest first.
+    pub prefixes: Vec<(String, u32)>,
+    /// A digit separator, like `_` in `1_000`.
+    pub separator: Option<char>,
+    /// Whether the separator may come right after a prefix, like in `0x_1F`.
+    pub separator_after_prefix: bool,
+    pub fractions: bool,
+    /// Characters starting an exponent, like `e` in `1e-9`.
+    pub exponents: String,
+    /// Type suffixes, like `u64` or `f32` in Rust.
+    pub suffixes: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawNumbers {
+    Enabled(bool),
+    Lexer(RawNumberLexer),
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawNumberLexer {
+    prefixes: HashMap<String, u32>,
+    separator: Option<char>,
+    separator_after_prefix: bool,
+    fractions: bool,
+    exponents: String,
+    suffixes: Vec<String>,
+}
+
+impl Default for RawNumberLexer {
+    fn default() -> Self {
+        Self {
+            prefixes: HashMap::new(),
+            separator: None,
+            separator_after_prefix: false,
+            fractions: true,
+            exponents: "eE".to_owned(),
+            suffixes: Vec::new(),
+        }
+    }
+}
+
+impl Default for Numbers {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            prefixes: Vec::new(),
+            separator: None,
+            separator_after_prefix: false,
+            fractions: true,
+            exponents: "eE".to_owned(),
+            suffixes: Vec::new(),
+        }
+    }
+}
+
+impl TryFrom<RawNumbers> for Numbers {
+    type Error = String;
+
+    fn try_from(raw: RawNumbers) -> Result<Self, Self::Error> {
+        let lexer = match raw {
+            RawNumbers::Enabled(enabled) => {
+                return Ok(Self {
+                    enabled,
+                    ..Self::default()
+                })
+            },
+            RawNumbers::Lexer(lexer) => lexer,
+        };
+
+        if let Some((prefix, radix)) = lexer
+            .prefixes
+            .iter()
+            .find(|(_, radix)| !(2..=36).contains(*radix))
+        {
+            return Err(format!("invalid radix {} for prefix `{}`", radix, prefix));
+        }
+
+        let mut prefixes: Vec<(String, u32)> = lexer.prefixes.into_iter().collect();
+        prefixes.sort_by_key(|(prefix, _)| cmp::Reverse(prefix.len()));
+
+        Ok(Self {
+            enabled: true,
+            prefixes,
+            separator: lexer.separator,
+            separator_after_prefix: lexer.separator_after_prefix,
+            fractions: lexer.fractions,
+            exponents: lexer.exponents,
+            suffixes: lexer.suffixes,
+        })
+    }
+}
+
+#[derive(Deserialize, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+struct Keywords {
+    primary: Vec<String>,
+    secondary: Vec<String>,
+    /// For languages like SQL, where keywords can be written in any case.
+    ignore_case: bool,
 }
 
 impl Default for FileType {
     fn default() -> Self {
         Self {
             name: String::from("No file type"),
+            extensions: Vec::new(),
+            filenames: Vec::new(),
+            shebangs: Vec::new(),
+            first_lines: Vec::new(),
             hl_opts: HighlightingOptions::default(),
         }
     }
@@ -32,186 +295,317 @@ impl FileType {
         &self.hl_opts
     }
 
-    pub fn from(file_name: &str) -> Self {
-        let file_name = file_name.to_lowercase();
+    fn matches_file_name(&self, file_name: &str) -> bool {
+        let base_name = Path::new(file_name)
+            .file_name()
+            .map_or_else(String::new, |name| name.to_string_lossy().to_lowercase());
 
-        if file_name.ends_with(".rs") {
-            Self {
-                name: "Rust".to_owned(),
-                hl_opts: HighlightingOptions::rust(),
-            }
-        } else if file_name.ends_with(".pas") {
-            Self {
-                name: "Pascal".to_owned(),
-                hl_opts: HighlightingOptions::default(),
-            }
-        } else if file_name.ends_with(".cpp") {
-            Self {
-                name: "C++".to_owned(),
-                hl_opts: HighlightingOptions::cpp(),
-            }
-        } else {
-            Self::default()
+        self.filenames
+            .iter()
+            .any(|name| name.to_lowercase() == base_name)
+            || self
+                .extensions
+                .iter()
+                .any(|extension| base_name.ends_with(&format!(".{}", extension.to_lowercase())))
+    }
+
+    /// Whether `program` runs this language, ignoring version suffixes like in `python3.11`.
+    fn matches_interpreter(&self, program: &str) -> bool {
+        let unversioned = program.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+
+        self.shebangs
+            .iter()
+            .any(|shebang| shebang == program || shebang == unversioned)
+    }
+
+    /// Whether `name` refers to this file type, as given in a modeline or by the user: its name,
+    /// one of its extensions or one of its interpreters.
+    fn matches_name(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name)
+            || self
+                .extensions
+                .iter()
+                .any(|extension| extension.eq_ignore_ascii_case(name))
+            || self.matches_interpreter(name)
+    }
+
+    fn matches_first_line(&self, line: &str) -> bool {
+        self.first_lines
+            .iter()
+            .any(|prefix| line.starts_with(prefix))
+    }
+}
+
+/// The program a `#!` line runs, looking through `env` and its options.
+fn shebang_program(first_line: &str) -> Option<&str> {
+    let mut words = first_line.strip_prefix("#!")?.split_whitespace();
+    let mut program = words.next()?.rsplit('/').next()?;
+    if program == "env" {
+        // `env -S interpreter args` and `env VAR=value interpreter` both run `interpreter`
+        program = words.find(|word| !word.starts_with('-') && !word.contains('='))?;
+        program = program.rsplit('/').next()?;
+    }
+
+    Some(program)
+}
+
+/// The file type named by a Vim (`vim: set ft=rust:`) or Emacs (`-*- mode: rust -*-`)
+/// modeline in `line`.
+fn modeline(line: &str) -> Option<&str> {
+    if let Some((_, rest)) = line.split_once("-*-") {
+        let (inner, _) = rest.split_once("-*-")?;
+        if !inner.contains(':') {
+            return Some(inner.trim());
         }
+
+        return inner.split(';').find_map(|variable| {
+            let (key, value) = variable.split_once(':')?;
+            key.trim()
+                .eq_ignore_ascii_case("mode")
+                .then(|| value.trim())
+        });
     }
+
+    let start = ["vim:", "vi:", "ex:"].iter().find_map(|marker| {
+        line.match_indices(marker)
+            .find(|(index, _)| {
+                *index == 0
+                    || line
+                        .get(..*index)
+                        .is_some_and(|before| before.ends_with(char::is_whitespace))
+            })
+            .map(|(index, _)| index.saturating_add(marker.len()))
+    })?;
+
+    line.get(start..)?
+        .split(|c: char| c == ':' || c.is_whitespace())
+        .find_map(|option| {
+            let (key, value) = option.split_once('=')?;
+            matches!(key, "ft" | "filetype" | "syntax").then_some(value)
+        })
 }
 
-impl HighlightingOptions {
-    pub fn rust() -> Self {
-        HighlightingOptions {
-            numbers: true,
-            strings: true,
-            chars: true,
-            comments: true,
-            multiline_comments: true,
-            primary_keywords: vec![
-                "as".to_owned(),
-                "break".to_owned(),
-                "const".to_owned(),
-                "continue".to_owned(),
-                "crate".to_owned(),
-                "else".to_owned(),
-                "enum".to_owned(),
-                "extern".to_owned(),
-                "false".to_owned(),
-                "fn".to_owned(),
-                "for".to_owned(),
-                "if".to_owned(),
-                "impl".to_owned(),
-                "in".to_owned(),
-                "let".to_owned(),
-                "loop".to_owned(),
-                "match".to_owned(),
-                "mod".to_owned(),
-                "move".to_owned(),
-                "mut".to_owned(),
-                "pub".to_owned(),
-                "ref".to_owned(),
-                "return".to_owned(),
-                "self".to_owned(),
-                "Self".to_owned(),
-                "static".to_owned(),
-                "struct".to_owned(),
-                "super".to_owned(),
-                "trait".to_owned(),
-                "true".to_owned(),
-                "type".to_owned(),
-                "unsafe".to_owned(),
-                "use".to_owned(),
-                "where".to_owned(),
-                "while".to_owned(),
-                "dyn".to_owned(),
-                "abstract".to_owned(),
-                "become".to_owned(),
-                "box".to_owned(),
-                "do".to_owned(),
-                "final".to_owned(),
-                "macro".to_owned(),
-                "override".to_owned(),
-                "priv".to_owned(),
-                "typeof".to_owned(),
-                "unsized".to_owned(),
-                "virtual".to_owned(),
-                "yield".to_owned(),
-                "async".to_owned(),
-                "await".to_owned(),
-                "try".to_owned(),
-            ],
-            secondary_keywords: vec![
-                "bool".to_owned(),
-                "char".to_owned(),
-                "i8".to_owned(),
-                "i16".to_owned(),
-                "i32".to_owned(),
-                "i64".to_owned(),
-                "isize".to_owned(),
-                "u8".to_owned(),
-                "u16".to_owned(),
-                "u32".to_owned(),
-                "u64".to_owned(),
-                "usize".to_owned(),
-                "f32".to_owned(),
-                "f64".to_owned(),
-            ],
+pub struct Languages {
+    file_types: Vec<FileType>,
+}
+
+impl Default for Languages {
+    fn default() -> Self {
+        let file_types = BUILTIN_LANGUAGES
+            .iter()
+            .map(|contents| toml::from_str(contents).expect("invalid built-in language"))
+            .collect();
+
+        Self { file_types }
+    }
+}
+
+impl Languages {
+    /// Adds every `*.toml` language definition found in `dir`, replacing built-in languages
+    /// of the same name. Problems are appended to `errors`.
+    pub async fn load_dir(&mut self, dir: &Path, errors: &mut Vec<String>) {
+        let Ok(mut entries) = fs::read_dir(dir).await else {
+            return;
+        };
+
+        let mut paths = Vec::new();
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let path = entry.path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "toml")
+            {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+
+        for path in paths {
+            let parsed = match fs::read_to_string(&path).await {
+                Ok(contents) => toml::from_str(&contents).map_err(|e| e.to_string()),
+                Err(e) => Err(e.to_string()),
+            };
+
+            match parsed {
+                Ok(file_type) => self.insert(file_type),
+                Err(e) => errors.push(format!("{}: {}", path.display(), e)),
+            }
         }
     }
 
-    pub fn pascal() -> Self {
-        Self {
-            numbers: true,
-            strings: false,
-            chars: false,
-            comments: false,
-            multiline_comments: false,
-            primary_keywords: Vec::new(),
-            secondary_keywords: Vec::new(),
+    fn insert(&mut self, file_type: FileType) {
+        if let Some(existing) = self
+            .file_types
+            .iter_mut()
+            .find(|existing| existing.name == file_type.name)
+        {
+            *existing = file_type;
+        } else {
+            self.file_types.push(file_type);
         }
     }
 
-    pub fn cpp() -> Self {
-        Self {
-            numbers: true,
-            strings: true,
-            chars: true,
-            comments: true,
-            multiline_comments: true,
-            primary_keywords: vec![
-                "const".to_owned(),
-                "for".to_owned(),
-                "while".to_owned(),
-                "do".to_owned(),
-                "if".to_owned(),
-                "else".to_owned(),
-                "using".to_owned(),
-                "include".to_owned(),
-                "define".to_owned(),
-                "namespace".to_owned(),
-                "typedef".to_owned(),
-                "return".to_owned(),
-            ],
-            secondary_keywords: vec![
-                "int".to_owned(),
-                "long".to_owned(),
-                "unsigned".to_owned(),
-                "float".to_owned(),
-                "double".to_owned(),
-                "bool".to_owned(),
-                "char".to_owned(),
-                "cin".to_owned(),
-                "cout".to_owned(),
-                "NULL".to_owned(),
-                "true".to_owned(),
-                "false".to_owned(),
-                "string".to_owned(),
-            ],
+    /// Registers the extensions of a `[[filetypes]]` config rule, which take precedence over
+    /// the ones in language definitions.
+    pub fn add_rule(&mut self, rule: &FileTypeRule) -> bool {
+        let Some(index) = self
+            .file_types
+            .iter()
+            .position(|file_type| file_type.name == rule.name)
+        else {
+            return false;
+        };
+
+        for file_type in &mut self.file_types {
+            file_type
+                .extensions
+                .retain(|extension| !rule.extensions.contains(extension));
         }
+        if let Some(file_type) = self.file_types.get_mut(index) {
+            file_type.extensions.extend(rule.extensions.iter().cloned());
+        }
+
+        true
+    }
+
+    pub fn find(&self, name: &str) -> Option<FileType> {
+        self.file_types
+            .iter()
+            .find(|file_type| file_type.name.eq_ignore_ascii_case(name))
+            .or_else(|| {
+                self.file_types
+                    .iter()
+                    .find(|file_type| file_type.matches_name(name))
+            })
+            .cloned()
+    }
+
+    /// Picks the file type for `file_name` given `lines`, the first and last few lines of the
+    /// file. A modeline wins over the file name, which wins over a shebang or the content.
+    pub fn detect(&self, file_name: &str, lines: &[&str]) -> FileType {
+        let from_modeline = || lines.iter().find_map(|line| self.find(modeline(line)?));
+        let from_file_name = || {
+            self.file_types
+                .iter()
+                .find(|file_type| file_type.matches_file_name(file_name))
+                .cloned()
+        };
+        let from_shebang = || {
+            let program = shebang_program(lines.first()?)?;
+            self.file_types
+                .iter()
+                .find(|file_type| file_type.matches_interpreter(program))
+                .cloned()
+        };
+        let from_content = || {
+            let line = lines.iter().find(|line| !line.trim().is_empty())?;
+            self.file_types
+                .iter()
+                .find(|file_type| file_type.matches_first_line(line))
+                .cloned()
+        };
+
+        from_modeline()
+            .or_else(from_file_name)
+            .or_else(from_shebang)
+            .or_else(from_content)
+            .unwrap_or_default()
     }
+}
 
-    pub fn numbers(&self) -> bool {
-        self.numbers
+impl HighlightingOptions {
+    pub fn numbers(&self) -> &Numbers {
+        &self.numbers
     }
 
-    pub fn strings(&self) -> bool {
-        self.strings
+    pub fn strings(&self) -> &[StringDelimiter] {
+        &self.strings
     }
 
     pub fn chars(&self) -> bool {
         self.chars
     }
 
-    pub fn comments(&self) -> bool {
-        self.comments
+    pub fn line_comments(&self) -> &[String] {
+        &self.line_comments
+    }
+
+    pub fn block_comments(&self) -> &[BlockComment] {
+        &self.block_comments
     }
 
     pub fn primary_keywords(&self) -> &Vec<String> {
-        &self.primary_keywords
+        &self.keywords.primary
     }
 
     pub fn secondary_keywords(&self) -> &Vec<String> {
-        &self.secondary_keywords
+        &self.keywords.secondary
+    }
+
+    pub fn keywords_ignore_case(&self) -> bool {
+        self.keywords.ignore_case
+    }
+
+    pub fn functions(&self) -> bool {
+        self.functions
+    }
+
+    pub fn types(&self) -> bool {
+        self.types
+    }
+
+    pub fn macro_suffix(&self) -> &str {
+        &self.macro_suffix
+    }
+
+    pub fn attributes(&self) -> &[String] {
+        &self.attributes
+    }
+
+    pub fn lifetimes(&self) -> bool {
+        self.lifetimes
     }
 
-    pub fn multiline_comments(&self) -> bool {
-        self.multiline_comments
+    pub fn operators(&self) -> &str {
+        &self.operators
+    }
+
+    pub fn punctuation(&self) -> &str {
+        &self.punctuation
+    }
+
+    pub fn indent(&self) -> &IndentRules {
+        &self.indent
+    }
+
+    pub fn paragraphs(&self) -> &ParagraphRules {
+        &self.paragraphs
+    }
+
+    pub fn closing_pair(&self, opener: char) -> Option<char> {
+        self.auto_pairs()
+            .into_iter()
+            .find_map(|(open, close)| (open == opener).then_some(close))
+    }
+
+    pub fn opening_pair(&self, closer: char) -> Option<char> {
+        self.auto_pairs()
+            .into_iter()
+            .find_map(|(open, close)| (close == closer).then_some(open))
+    }
+
+    fn auto_pairs(&self) -> Vec<(char, char)> {
+        let pair = |pair: &str| {
+            let mut chars = pair.chars();
+            match (chars.next(), chars.next(), chars.next()) {
+                (Some(open), Some(close), None) => Some((open, close)),
+                _ => None,
+            }
+        };
+
+        match &self.auto_pairs {
+            Some(pairs) => pairs.iter().filter_map(|p| pair(p)).collect(),
+            None => DEFAULT_AUTO_PAIRS.iter().filter_map(|p| pair(p)).collect(),
+        }
     }
 }
@@ -1,6 +1,4 @@
-use crossterm::style::Color;
-
-#[derive(PartialEq, Clone, Copy)]
+#[derive(PartialEq, Eq, Hash, Clone, Copy)]
 pub enum Type {
     None,
     Number,
@@ -11,19 +9,67 @@ pub enum Type {
     MultilineComment,
     PrimaryKeywords,
     SecondaryKeywords,
+    Function,
+    TypeName,
+    Macro,
+    Attribute,
+    Lifetime,
+    Operator,
+    Punctuation,
+    Escape,
+    Todo,
 }
 
 impl Type {
-    pub fn to_color(self) -> Color {
-        match self {
-            Type::Number => Color::Yellow,
-            Type::Match => Color::Blue,
-            Type::String => Color::Green,
-            Type::Char => Color::Yellow,
-            Type::Comment | Type::MultilineComment => Color::DarkGrey,
-            Type::PrimaryKeywords => Color::Magenta,
-            Type::SecondaryKeywords => Color::Yellow,
-            Type::None => Color::Reset,
+    /// The name used for this type in theme files.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "number" => Some(Type::Number),
+            "string" => Some(Type::String),
+            "char" => Some(Type::Char),
+            "comment" => Some(Type::Comment),
+            "multiline_comment" => Some(Type::MultilineComment),
+            "primary_keywords" => Some(Type::PrimaryKeywords),
+            "secondary_keywords" => Some(Type::SecondaryKeywords),
+            "function" => Some(Type::Function),
+            "type" => Some(Type::TypeName),
+            "macro" => Some(Type::Macro),
+            "attribute" => Some(Type::Attribute),
+            "lifetime" => Some(Type::Lifetime),
+            "operator" => Some(Type::Operator),
+            "punctuation" => Some(Type::Punctuation),
+            "escape" => Some(Type::Escape),
+            "todo" => Some(Type::Todo),
+            _ => None,
         }
     }
+
+    /// Whether this is part of a string or a comment.
+    pub fn is_literal(self) -> bool {
+        matches!(
+            self,
+            Type::String
+                | Type::Char
+                | Type::Escape
+                | Type::Comment
+                | Type::MultilineComment
+                | Type::Todo
+        )
+    }
+}
+
+/// What a row ends inside of, so that the next row can carry on from there.
+#[derive(PartialEq, Eq, Clone, Copy, Default)]
+pub enum State {
+    #[default]
+    Normal,
+    /// `depth` counts the comments opened inside the outermost one.
+    BlockComment {
+        rule: usize,
+        depth: usize,
+    },
+    String {
+        rule: usize,
+        hashes: usize,
+    },
 }
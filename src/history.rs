@@ -0,0 +1,79 @@
+use crate::Position;
+
+/// Rows `start..start + removed.len()` being replaced by `inserted`.
+#[derive(Clone)]
+pub struct Change {
+    pub start: usize,
+    pub removed: Vec<String>,
+    pub inserted: Vec<String>,
+}
+
+/// The changes undone and redone together, with where the cursor was before and after them.
+#[derive(Clone)]
+pub struct Group {
+    pub changes: Vec<Change>,
+    pub cursor_before: Position,
+    pub cursor_after: Position,
+}
+
+#[derive(Default)]
+pub struct History {
+    undo: Vec<Group>,
+    redo: Vec<Group>,
+    /// Whether changes are added to the last group rather than starting a new one.
+    open: bool,
+    /// Whether the last checkpoint was for typing, which keeps the group open.
+    typing: bool,
+    cursor: Position,
+}
+
+impl History {
+    /// Makes the next change start a new group, unless both this and the previous checkpoint
+    /// are for typing.
+    pub fn checkpoint(&mut self, cursor: &Position, typing: bool) {
+        if !typing || !self.typing {
+            self.open = false;
+            self.cursor.clone_from(cursor);
+        }
+        self.typing = typing;
+    }
+
+    pub fn record(&mut self, change: Change) {
+        self.redo.clear();
+
+        if !self.open || self.undo.is_empty() {
+            self.undo.push(Group {
+                changes: Vec::new(),
+                cursor_before: self.cursor.clone(),
+                cursor_after: self.cursor.clone(),
+            });
+            self.open = true;
+        }
+
+        if let Some(group) = self.undo.last_mut() {
+            group.changes.push(change);
+        }
+    }
+
+    pub fn set_cursor(&mut self, cursor: &Position) {
+        if let Some(group) = self.undo.last_mut().filter(|_| self.open) {
+            group.cursor_after.clone_from(cursor);
+        }
+    }
+
+    pub fn undo(&mut self) -> Option<Group> {
+        let group = self.undo.pop()?;
+        self.redo.push(group.clone());
+        self.open = false;
+
+        Some(group)
+    }
+
+    pub fn redo(&mut self) -> Option<Group> {
+        let group = self.redo.pop()?;
+        self.undo.push(group.clone());
+        self.open = false;
+
+        Some(group)
+    }
+}
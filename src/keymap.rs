@@ -0,0 +1,350 @@
+use std::collections::HashMap;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum Action {
+    Quit,
+    Save,
+    Find,
+    Open,
+    ReloadConfig,
+    SetFileType,
+    JumpToBracket,
+    ToggleComment,
+    Undo,
+    Redo,
+    DuplicateLine,
+    MoveLineUp,
+    MoveLineDown,
+    JoinLines,
+    DeleteLine,
+    SortLines,
+    UniqueLines,
+    ReverseLines,
+    Reflow,
+    SplitDown,
+    SplitRight,
+    CloseWindow,
+    NextWindow,
+    PreviousWindow,
+    GrowWindow,
+    ShrinkWindow,
+    NextTab,
+    PreviousTab,
+    MoveTabLeft,
+    MoveTabRight,
+}
+
+impl Action {
+    const ALL: [Self; 30] = [
+        Self::Quit,
+        Self::Save,
+        Self::Find,
+        Self::Open,
+        Self::ReloadConfig,
+        Self::SetFileType,
+        Self::JumpToBracket,
+        Self::ToggleComment,
+        Self::Undo,
+        Self::Redo,
+        Self::DuplicateLine,
+        Self::MoveLineUp,
+        Self::MoveLineDown,
+        Self::JoinLines,
+        Self::DeleteLine,
+        Self::SortLines,
+        Self::UniqueLines,
+        Self::ReverseLines,
+        Self::Reflow,
+        Self::SplitDown,
+        Self::SplitRight,
+        Self::CloseWindow,
+        Self::NextWindow,
+        Self::PreviousWindow,
+        Self::GrowWindow,
+        Self::ShrinkWindow,
+        Self::NextTab,
+        Self::PreviousTab,
+        Self::MoveTabLeft,
+        Self::MoveTabRight,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Quit => "quit",
+            Self::Save => "save",
+            Self::Find => "find",
+            Self::Open => "open",
+            Self::ReloadConfig => "reload_config",
+            Self::SetFileType => "set_file_type",
+            Self::JumpToBracket => "jump_to_bracket",
+            Self::ToggleComment => "toggle_comment",
+            Self::Undo => "undo",
+            Self::Redo => "redo",
+            Self::DuplicateLine => "duplicate_line",
+            Self::MoveLineUp => "move_line_up",
+            Self::MoveLineDown => "move_line_down",
+            Self::JoinLines => "join_lines",
+            Self::DeleteLine => "delete_line",
+            Self::SortLines => "sort_lines",
+            Self::UniqueLines => "unique_lines",
+            Self::ReverseLines => "reverse_lines",
+            Self::Reflow => "reflow",
+            Self::SplitDown => "split_down",
+            Self::SplitRight => "split_right",
+            Self::CloseWindow => "close_window",
+            Self::NextWindow => "next_window",
+            Self::PreviousWindow => "previous_window",
+            Self::GrowWindow => "grow_window",
+            Self::ShrinkWindow => "shrink_window",
+            Self::NextTab => "next_tab",
+            Self::PreviousTab => "previous_tab",
+            Self::MoveTabLeft => "move_tab_left",
+            Self::MoveTabRight => "move_tab_right",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|action| action.name() == name)
+    }
+
+    fn default_keys(self) -> &'static [&'static str] {
+        match self {
+            Self::Quit => &["ctrl-q", "esc"],
+            Self::Save => &["ctrl-s"],
+            Self::Find => &["ctrl-f"],
+            Self::Open => &["ctrl-o"],
+            Self::ReloadConfig => &["ctrl-r"],
+            Self::SetFileType => &["ctrl-t"],
+            Self::JumpToBracket => &["ctrl-b"],
+            // Most terminals send Ctrl-/ as Ctrl-7
+            Self::ToggleComment => &["ctrl-/", "ctrl-7"],
+            Self::Undo => &["ctrl-z"],
+            Self::Redo => &["ctrl-y"],
+            Self::DuplicateLine => &["ctrl-d"],
+            Self::MoveLineUp => &["alt-up"],
+            Self::MoveLineDown => &["alt-down"],
+            Self::JoinLines => &["ctrl-j"],
+            Self::DeleteLine => &["ctrl-k"],
+            Self::SortLines => &["alt-s"],
+            Self::UniqueLines => &["alt-u"],
+            Self::ReverseLines => &["alt-r"],
+            Self::Reflow => &["alt-q"],
+            Self::SplitDown => &["alt--"],
+            Self::SplitRight => &["alt-\\"],
+            Self::CloseWindow => &["ctrl-w"],
+            Self::NextWindow => &["alt-n"],
+            Self::PreviousWindow => &["alt-p"],
+            Self::GrowWindow => &["alt-."],
+            Self::ShrinkWindow => &["alt-,"],
+            Self::NextTab => &["alt-right"],
+            Self::PreviousTab => &["alt-left"],
+            Self::MoveTabLeft => &["alt-shift-left"],
+            Self::MoveTabRight => &["alt-shift-right"],
+        }
+    }
+}
+
+pub struct Keymap {
+    bindings: HashMap<KeyEvent, Action>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let mut keymap = Self {
+            bindings: HashMap::new(),
+        };
+
+        for action in Action::ALL {
+            for key in action.default_keys() {
+                if let Some(key) = parse_key(key) {
+                    keymap.bindings.insert(key, action);
+                }
+            }
+        }
+
+        keymap
+    }
+}
+
+impl Keymap {
+    pub fn action(&self, key: &KeyEvent) -> Option<Action> {
+        self.bindings.get(key).copied()
+    }
+
+    /// The keys bound to `action`, described like `Ctrl-S`, in a stable order.
+    pub fn keys(&self, action: Action) -> Vec<String> {
+        let mut keys: Vec<String> = self
+            .bindings
+            .iter()
+            .filter(|(_, bound)| **bound == action)
+            .map(|(key, _)| describe_key(key))
+            .collect();
+        keys.sort();
+
+        keys
+    }
+
+    pub fn bind(&mut self, action: Action, keys: Vec<KeyEvent>) {
+        self.bindings.retain(|_, bound| *bound != action);
+
+        for key in keys {
+            self.bindings.insert(key, action);
+        }
+    }
+}
+
+/// Parses key descriptions such as `ctrl-s`, `alt-shift-x`, `esc` or `f5`. Letters keep their
+/// case, and shifted ones are uppercase, as terminals report them.
+pub fn parse_key(description: &str) -> Option<KeyEvent> {
+    let description = description.trim();
+    let mut modifiers = KeyModifiers::NONE;
+    let mut parts: Vec<&str> = description.split('-').collect();
+
+    // A trailing empty part means the key itself is '-', e.g. `ctrl--`
+    let key = if description.ends_with("--") || description == "-" {
+        parts.truncate(parts.len().saturating_sub(2));
+        "-"
+    } else {
+        parts.pop()?
+    };
+
+    for modifier in parts {
+        match modifier.to_lowercase().as_str() {
+            "ctrl" => modifiers.insert(KeyModifiers::CONTROL),
+            "alt" => modifiers.insert(KeyModifiers::ALT),
+            "shift" => modifiers.insert(KeyModifiers::SHIFT),
+            _ => return None,
+        }
+    }
+
+    let code = match key.to_lowercase().as_str() {
+        "esc" | "escape" => KeyCode::Esc,
+        "enter" | "return" => KeyCode::Enter,
+        "tab" => KeyCode::Tab,
+        "backtab" => KeyCode::BackTab,
+        "backspace" => KeyCode::Backspace,
+        "delete" | "del" => KeyCode::Delete,
+        "insert" | "ins" => KeyCode::Insert,
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" => KeyCode::PageUp,
+        "pagedown" => KeyCode::PageDown,
+        "space" => KeyCode::Char(' '),
+        _ => {
+            let mut chars = key.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => char_key(c, &mut modifiers),
+                (Some('f' | 'F'), Some(_)) => KeyCode::F(key.get(1..)?.parse().ok()?),
+                _ => return None,
+            }
+        },
+    };
+
+    Some(KeyEvent::new(code, modifiers))
+}
+
+/// Describes `key` for display, with capitalized names like `Ctrl-S`.
+pub fn describe_key(key: &KeyEvent) -> String {
+    let mut description = String::new();
+    for (modifier, name) in [
+        (KeyModifiers::CONTROL, "Ctrl-"),
+        (KeyModifiers::ALT, "Alt-"),
+        (KeyModifiers::SHIFT, "Shift-"),
+    ] {
+        if key.modifiers.contains(modifier) {
+            description.push_str(name);
+        }
+    }
+
+    let name = match key.code {
+        KeyCode::Esc => "Esc".to_owned(),
+        KeyCode::Enter => "Enter".to_owned(),
+        KeyCode::Tab => "Tab".to_owned(),
+        KeyCode::BackTab => "BackTab".to_owned(),
+        KeyCode::Backspace => "Backspace".to_owned(),
+        KeyCode::Delete => "Delete".to_owned(),
+        KeyCode::Insert => "Insert".to_owned(),
+        KeyCode::Up => "Up".to_owned(),
+        KeyCode::Down => "Down".to_owned(),
+        KeyCode::Left => "Left".to_owned(),
+        KeyCode::Right => "Right".to_owned(),
+        KeyCode::Home => "Home".to_owned(),
+        KeyCode::End => "End".to_owned(),
+        KeyCode::PageUp => "PageUp".to_owned(),
+        KeyCode::PageDown => "PageDown".to_owned(),
+        KeyCode::Char(' ') => "Space".to_owned(),
+        KeyCode::Char(c) => c.to_uppercase().collect(),
+        KeyCode::F(number) => format!("F{}", number),
+        KeyCode::Null => "Null".to_owned(),
+    };
+    description.push_str(&name);
+
+    description
+}
+
+/// Uppercases `c` when shifted, or marks an uppercase `c` as shifted.
+fn char_key(c: char, modifiers: &mut KeyModifiers) -> KeyCode {
+    if c.is_uppercase() {
+        modifiers.insert(KeyModifiers::SHIFT);
+    }
+
+    let mut upper = c.to_uppercase();
+    match (upper.next(), upper.next()) {
+        (Some(upper), None) if modifiers.contains(KeyModifiers::SHIFT) => KeyCode::Char(upper),
+        _ => KeyCode::Char(c),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shifted_letters_are_uppercase() {
+        let shift_a = KeyEvent::new(KeyCode::Char('A'), KeyModifiers::SHIFT);
+        assert_eq!(parse_key("shift-a"), Some(shift_a));
+        assert_eq!(parse_key("A"), Some(shift_a));
+        assert_eq!(parse_key("Shift-A"), Some(shift_a));
+    }
+
+    #[test]
+    fn describes_keys_for_display() {
+        for (key, description) in [
+            ("ctrl-s", "Ctrl-S"),
+            ("alt-shift-left", "Alt-Shift-Left"),
+            ("esc", "Esc"),
+            ("f5", "F5"),
+            ("ctrl--", "Ctrl--"),
+            ("A", "Shift-A"),
+        ] {
+            let key = parse_key(key).expect("invalid key");
+            assert_eq!(describe_key(&key), description);
+        }
+    }
+
+    #[test]
+    fn named_keys_ignore_case() {
+        assert_eq!(
+            parse_key("Ctrl-Esc"),
+            Some(KeyEvent::new(KeyCode::Esc, KeyModifiers::CONTROL))
+        );
+        assert_eq!(
+            parse_key("F5"),
+            Some(KeyEvent::new(KeyCode::F(5), KeyModifiers::NONE))
+        );
+        assert_eq!(
+            parse_key("ctrl--"),
+            Some(KeyEvent::new(KeyCode::Char('-'), KeyModifiers::CONTROL))
+        );
+        assert_eq!(
+            parse_key("ctrl-s"),
+            Some(KeyEvent::new(KeyCode::Char('s'), KeyModifiers::CONTROL))
+        );
+    }
+}
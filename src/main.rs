@@ -17,13 +17,20 @@
     clippy::case_sensitive_file_extension_comparisons,
     clippy::struct_excessive_bools
 )]
+mod config;
 mod discord_rpc;
 mod document;
 mod editor;
+mod editorconfig;
 mod filetype;
 mod highlighting;
+mod history;
+mod keymap;
 mod row;
+mod syntax_tree;
 mod terminal;
+mod theme;
+mod window;
 
 pub use discord_rpc::Rpc;
 pub use document::Document;
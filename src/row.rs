@@ -1,15 +1,26 @@
 use std::cmp;
+use std::ops::Range;
 
-use crossterm::style::Stylize;
 use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 
+use crate::filetype::Numbers;
+use crate::theme::{Theme, UiElement};
 use crate::{highlighting, HighlightingOptions, SearchDirection};
 
+const TODO_MARKERS: [&str; 5] = ["TODO", "FIXME", "XXX", "HACK", "NOTE"];
+
 #[derive(Default)]
 pub struct Row {
     string: String,
+    /// One type per grapheme. Telling code from strings and comments relies on it.
     highlighting: Vec<highlighting::Type>,
     pub is_highlighted: bool,
+    start_state: highlighting::State,
+    end_state: highlighting::State,
+    /// Whether the row starts inside a string or a comment carried over from the row above.
+    starts_in_literal: bool,
+    /// The number of graphemes, kept in sync with `string`.
     len: usize,
 }
 
@@ -19,43 +30,95 @@ impl From<&str> for Row {
             string: slice.to_owned(),
             highlighting: Vec::new(),
             is_highlighted: false,
+            start_state: highlighting::State::Normal,
+            end_state: highlighting::State::Normal,
+            starts_in_literal: false,
             len: slice.graphemes(true).count(),
         }
     }
 }
 
 impl Row {
-    pub fn render(&self, start: usize, end: usize) -> String {
-        let end = cmp::min(end, self.string.len());
-        let start = cmp::min(start, end);
+    /// Tabs and wide graphemes cut off by the edges of `columns` are drawn as spaces.
+    pub fn render(
+        &self,
+        columns: Range<usize>,
+        tab_width: usize,
+        theme: &Theme,
+        brackets: &[usize],
+        selection: &Range<usize>,
+    ) -> String {
         let mut result = String::new();
+        let mut column = 0;
 
-        #[allow(clippy::integer_arithmetic)]
-        for (index, grapheme) in self
-            .string
-            .graphemes(true)
-            .enumerate()
-            .skip(start)
-            .take(end - start)
-        {
-            if let Some(c) = grapheme.chars().next() {
+        for (index, grapheme) in self.string.graphemes(true).enumerate() {
+            if column >= columns.end {
+                break;
+            }
+            let width = grapheme_width(grapheme, column, tab_width);
+            let next = column.saturating_add(width);
+            let visible = if width == 0 {
+                column >= columns.start
+            } else {
+                next > columns.start
+            };
+
+            if visible {
                 let highlighting_type = self
                     .highlighting
                     .get(index)
                     .unwrap_or(&highlighting::Type::None);
 
-                let highlight = format!("{}", c.with(highlighting_type.to_color()));
-                result.push_str(&highlight);
+                let mut style = if brackets.contains(&index) {
+                    theme.ui(UiElement::MatchingBracket)
+                } else {
+                    theme.syntax(*highlighting_type)
+                };
+                if selection.contains(&index) {
+                    style = theme.layer(style, UiElement::Selection);
+                }
 
-                if c == '\t' {
-                    result.push(' ');
+                if grapheme == "\t" || column < columns.start || next > columns.end {
+                    let cells = next
+                        .min(columns.end)
+                        .saturating_sub(column.max(columns.start));
+                    result.push_str(&style.apply(" ".repeat(cells)).to_string());
+                } else {
+                    result.push_str(&style.apply(grapheme).to_string());
                 }
             }
+
+            column = next;
         }
 
         result
     }
 
+    /// The display column at which grapheme `at` starts, or the width of the row past its end.
+    pub fn column(&self, at: usize, tab_width: usize) -> usize {
+        self.string
+            .graphemes(true)
+            .take(at)
+            .fold(0, |column, grapheme| {
+                column.saturating_add(grapheme_width(grapheme, column, tab_width))
+            })
+    }
+
+    /// The grapheme covering display column `column`, or the length of the row past its end.
+    pub fn index_at_column(&self, column: usize, tab_width: usize) -> usize {
+        let mut start: usize = 0;
+
+        for (index, grapheme) in self.string.graphemes(true).enumerate() {
+            let end = start.saturating_add(grapheme_width(grapheme, start, tab_width));
+            if end > column {
+                return index;
+            }
+            start = end;
+        }
+
+        self.len
+    }
+
     pub fn len(&self) -> usize {
         self.len
     }
@@ -64,89 +127,181 @@ impl Row {
         self.len == 0
     }
 
-    #[allow(clippy::integer_arithmetic)]
     pub fn insert(&mut self, at: usize, c: char) {
+        let index = self.byte_index(at);
+        self.string.insert(index, c);
+        self.update_len();
+    }
+
+    pub fn delete(&mut self, at: usize) {
         if at >= self.len() {
-            self.string.push(c);
-            self.len += 1;
             return;
         }
 
-        let mut result = String::new();
-        let mut len = 0;
+        let start = self.byte_index(at);
+        let end = start.saturating_add(self.grapheme_len(at));
+        self.string.replace_range(start..end, "");
+        self.update_len();
+    }
 
-        for (index, grapheme) in self.string.graphemes(true).enumerate() {
-            len += 1;
+    pub fn append(&mut self, new: &Self) {
+        self.string.push_str(&new.string);
+        self.update_len();
+    }
 
-            if index == at {
-                len += 1;
-                result.push(c);
-            }
+    pub fn split(&mut self, at: usize) -> Self {
+        let splitted_row = self.string.split_off(self.byte_index(at));
+        self.update_len();
+        self.is_highlighted = false;
 
-            result.push_str(grapheme);
-        }
+        Self::from(splitted_row.as_str())
+    }
 
-        self.len = len;
-        self.string = result;
+    /// Recounts the graphemes after an edit, which can merge neighbouring ones, such as a
+    /// combining mark typed after a letter.
+    fn update_len(&mut self) {
+        self.len = self.string.graphemes(true).count();
     }
 
-    #[allow(clippy::integer_arithmetic)]
-    pub fn delete(&mut self, at: usize) {
-        if at >= self.len() {
-            return;
-        }
+    pub fn as_bytes(&self) -> &[u8] {
+        self.string.as_bytes()
+    }
 
-        let mut result = String::new();
-        let mut len = 0;
+    pub fn as_str(&self) -> &str {
+        &self.string
+    }
+
+    /// The brackets outside of strings and comments, with their grapheme index.
+    pub fn brackets(&self) -> Vec<(usize, char)> {
+        let mut brackets = Vec::new();
 
         for (index, grapheme) in self.string.graphemes(true).enumerate() {
-            if index != at {
-                len += 1;
-                result.push_str(grapheme);
+            if let Some(c) = grapheme.chars().next() {
+                if !self.is_literal_at(index)
+                    && "()[]{}".contains(c)
+                    && grapheme.len() == c.len_utf8()
+                {
+                    brackets.push((index, c));
+                }
             }
         }
 
-        self.len = len;
-        self.string = result;
+        brackets
     }
 
-    #[allow(clippy::integer_arithmetic)]
-    pub fn append(&mut self, new: &Self) {
-        self.string = format!("{}{}", self.string, new.string);
-        self.len += new.len;
+    /// The text before grapheme `at` without comments, strings and trailing whitespace.
+    pub fn code_before(&self, at: usize) -> String {
+        let mut code = String::new();
+
+        for (index, grapheme) in self.string.graphemes(true).take(at).enumerate() {
+            if !self.is_literal_at(index) {
+                code.push_str(grapheme);
+            }
+        }
+
+        code.truncate(code.trim_end().len());
+        code
     }
 
-    #[allow(clippy::integer_arithmetic)]
-    pub fn split(&mut self, at: usize) -> Self {
-        let mut row = String::new();
-        let mut len = 0;
-        let mut splitted_row = String::new();
-        let mut splitted_length = 0;
+    /// The byte offset at which the grapheme at `at` starts, or the length of the row past it.
+    pub fn byte_index(&self, at: usize) -> usize {
+        self.string
+            .grapheme_indices(true)
+            .nth(at)
+            .map_or(self.string.len(), |(index, _)| index)
+    }
 
-        for (index, grapheme) in self.string.graphemes(true).enumerate() {
-            if index < at {
-                len += 1;
-                row.push_str(grapheme);
-            } else {
-                splitted_length += 1;
-                splitted_row.push_str(grapheme);
+    /// The index of the char containing byte `byte_index`, as taken by `set_highlighting`.
+    pub fn char_index(&self, byte_index: usize) -> usize {
+        self.string
+            .char_indices()
+            .take_while(|(index, _)| *index < byte_index)
+            .count()
+    }
+
+    /// The graphemes starting each of the lines the row is broken into to fit `width`
+    /// columns, breaking after whitespace where possible. The first one is always 0.
+    #[allow(clippy::integer_arithmetic, clippy::indexing_slicing)]
+    pub fn wrap_points(&self, width: usize, tab_width: usize) -> Vec<usize> {
+        let width = width.max(1);
+        let graphemes: Vec<&str> = self.string.graphemes(true).collect();
+        let mut columns = vec![0];
+        for grapheme in &graphemes {
+            let column = columns[columns.len() - 1];
+            columns.push(column + grapheme_width(grapheme, column, tab_width));
+        }
+
+        let mut starts = vec![0];
+        let mut start = 0;
+
+        while columns[graphemes.len()] - columns[start] > width {
+            // Every line takes at least one grapheme, even one wider than the screen
+            let mut end = start + 1;
+            while end < graphemes.len() && columns[end + 1] - columns[start] <= width {
+                end += 1;
             }
+
+            // Whitespace right after the line can hang off its end, where it is not drawn
+            start = (start + 1..=cmp::min(end + 1, graphemes.len()))
+                .rev()
+                .find(|index| graphemes[index - 1].chars().all(char::is_whitespace))
+                .unwrap_or(end);
+            starts.push(start);
         }
 
-        self.string = row;
-        self.len = len;
-        self.is_highlighted = false;
+        starts
+    }
 
-        Self {
-            string: splitted_row,
-            highlighting: Vec::new(),
-            is_highlighted: false,
-            len: splitted_length,
+    pub fn grapheme(&self, at: usize) -> Option<&str> {
+        self.string.graphemes(true).nth(at)
+    }
+
+    pub fn is_literal_at(&self, at: usize) -> bool {
+        self.hl_type_at(at).is_literal()
+    }
+
+    /// Whether text typed at grapheme `at` ends up in a string or a comment.
+    pub fn is_in_literal(&self, at: usize) -> bool {
+        let Some(before) = at.checked_sub(1) else {
+            return self.starts_in_literal;
+        };
+        if !self.is_literal_at(before) {
+            return false;
+        }
+
+        if at < self.len {
+            self.is_literal_at(at)
+        } else {
+            // Only comments and unterminated strings go on past the end of the row
+            self.end_state != highlighting::State::Normal
+                || matches!(
+                    self.hl_type_at(before),
+                    highlighting::Type::Comment | highlighting::Type::Todo
+                )
         }
     }
 
-    pub fn as_bytes(&self) -> &[u8] {
-        self.string.as_bytes()
+    fn hl_type_at(&self, at: usize) -> highlighting::Type {
+        self.highlighting
+            .get(at)
+            .copied()
+            .unwrap_or(highlighting::Type::None)
+    }
+
+    pub fn grapheme_len(&self, at: usize) -> usize {
+        self.string.graphemes(true).nth(at).map_or(0, str::len)
+    }
+
+    /// Strips trailing whitespace, returning it if there was any.
+    pub fn trim_end(&mut self) -> Option<String> {
+        let trimmed_len = self.string.trim_end().len();
+        if trimmed_len == self.string.len() {
+            return None;
+        }
+
+        let trimmed = self.string.split_off(trimmed_len);
+        self.len = self.string.graphemes(true).count();
+        Some(trimmed)
     }
 
     pub fn find(&self, query: &str, at: usize, direction: SearchDirection) -> Option<usize> {
@@ -173,22 +328,20 @@ impl Row {
             .take(end - start)
             .collect();
 
-        let matching_byte_index = if direction == SearchDirection::Forward {
-            substring.find(query)
+        // Matches starting inside a grapheme are skipped rather than ending the search
+        let mut matches = substring
+            .grapheme_indices(true)
+            .enumerate()
+            .filter(|(_, (byte_index, _))| substring[*byte_index..].starts_with(query))
+            .map(|(grapheme_index, _)| grapheme_index);
+        let matching_index = if direction == SearchDirection::Forward {
+            matches.next()
         } else {
-            substring.rfind(query)
+            matches.last()
         };
 
-        if let Some(matching_byte_index) = matching_byte_index {
-            for (grapheme_index, (byte_index, _)) in substring.grapheme_indices(true).enumerate() {
-                if matching_byte_index == byte_index {
-                    #[allow(clippy::integer_arithmetic)]
-                    return Some(start + grapheme_index);
-                }
-            }
-        }
-
-        None
+        #[allow(clippy::integer_arithmetic)]
+        matching_index.map(|grapheme_index| start + grapheme_index)
     }
 
     fn highlight_match(&mut self, word: &Option<String>) {
@@ -200,9 +353,13 @@ impl Row {
             let mut index = 0;
             while let Some(search_match) = self.find(word, index, SearchDirection::Forward) {
                 if let Some(next_index) = search_match.checked_add(word.graphemes(true).count()) {
-                    #[allow(clippy::indexing_slicing)]
-                    for i in search_match..next_index {
-                        self.highlighting[i] = highlighting::Type::Match;
+                    for hl_type in self
+                        .highlighting
+                        .iter_mut()
+                        .take(next_index)
+                        .skip(search_match)
+                    {
+                        *hl_type = highlighting::Type::Match;
                     }
                     index = next_index;
                 } else {
@@ -219,6 +376,7 @@ impl Row {
         substring: &str,
         chars: &[char],
         hl_type: highlighting::Type,
+        ignore_case: bool,
     ) -> bool {
         if substring.is_empty() {
             return false;
@@ -226,7 +384,7 @@ impl Row {
 
         for (substring_index, c) in substring.chars().enumerate() {
             if let Some(next_char) = chars.get(index.saturating_add(substring_index)) {
-                if *next_char != c {
+                if *next_char != c && !(ignore_case && next_char.eq_ignore_ascii_case(&c)) {
                     return false;
                 }
             } else {
@@ -234,7 +392,7 @@ impl Row {
             }
         }
 
-        for _ in 0..substring.len() {
+        for _ in substring.chars() {
             self.highlighting.push(hl_type);
             *index += 1;
         }
@@ -248,6 +406,7 @@ impl Row {
         chars: &[char],
         keywords: &[String],
         hl_type: highlighting::Type,
+        ignore_case: bool,
     ) -> bool {
         if *index > 0 {
             #[allow(clippy::indexing_slicing, clippy::integer_arithmetic)]
@@ -258,16 +417,12 @@ impl Row {
         }
 
         for word in keywords {
-            if *index < chars.len().saturating_sub(word.len()) {
-                #[allow(clippy::indexing_slicing, clippy::integer_arithmetic)]
-                let next_char = chars[*index + word.len()];
-
-                if !is_separator(next_char) {
-                    continue;
-                }
+            let after_word = index.saturating_add(word.chars().count());
+            if chars.get(after_word).is_some_and(|c| !is_separator(*c)) {
+                continue;
             }
 
-            if self.highlight_str(index, word, chars, hl_type) {
+            if self.highlight_str(index, word, chars, hl_type, ignore_case) {
                 return true;
             }
         }
@@ -285,6 +440,7 @@ impl Row {
             chars,
             opts.primary_keywords(),
             highlighting::Type::PrimaryKeywords,
+            opts.keywords_ignore_case(),
         )
     }
 
@@ -299,6 +455,7 @@ impl Row {
             chars,
             opts.secondary_keywords(),
             highlighting::Type::SecondaryKeywords,
+            opts.keywords_ignore_case(),
         )
     }
 
@@ -338,197 +495,1031 @@ impl Row {
         &mut self,
         index: &mut usize,
         opts: &HighlightingOptions,
-        c: char,
         chars: &[char],
     ) -> bool {
-        if opts.comments() && c == '/' && *index < chars.len() {
-            if let Some(next_char) = chars.get(index.saturating_add(1)) {
-                if *next_char == '/' {
-                    for _ in *index..chars.len() {
-                        self.highlighting.push(highlighting::Type::Comment);
-                        *index += 1;
-                    }
-
-                    return true;
-                }
+        if opts
+            .line_comments()
+            .iter()
+            .any(|token| starts_with_at(chars, *index, token))
+        {
+            for _ in *index..chars.len() {
+                self.highlighting.push(highlighting::Type::Comment);
+                *index += 1;
             }
+
+            return true;
         }
 
         false
     }
 
-    #[allow(
-        clippy::indexing_slicing,
-        clippy::string_slice,
-        clippy::integer_arithmetic
-    )]
     fn highlight_multiline_comment(
         &mut self,
         index: &mut usize,
         opts: &HighlightingOptions,
-        c: char,
         chars: &[char],
-    ) -> bool {
-        if opts.comments() && c == '/' && *index < chars.len() {
-            if let Some(next_char) = chars.get(index.saturating_add(1)) {
-                if *next_char == '*' {
-                    let closing_index =
-                        if let Some(closing_index) = self.string[*index + 2..].find("*/") {
-                            *index + closing_index + 4
-                        } else {
-                            chars.len()
-                        };
-                    for _ in *index..closing_index {
-                        self.highlighting.push(highlighting::Type::MultilineComment);
-                        *index += 1;
-                    }
+    ) -> Option<highlighting::State> {
+        let (rule, comment) = opts
+            .block_comments()
+            .iter()
+            .enumerate()
+            .filter(|(_, comment)| starts_with_at(chars, *index, &comment.start))
+            .max_by_key(|(_, comment)| comment.start.len())?;
+
+        self.push_highlighting(
+            index,
+            comment.start.chars().count(),
+            highlighting::Type::MultilineComment,
+            chars.len(),
+        );
+
+        Some(self.continue_multiline_comment(index, opts, chars, rule, 0))
+    }
 
-                    return true;
+    fn continue_multiline_comment(
+        &mut self,
+        index: &mut usize,
+        opts: &HighlightingOptions,
+        chars: &[char],
+        rule: usize,
+        mut depth: usize,
+    ) -> highlighting::State {
+        let Some(comment) = opts.block_comments().get(rule) else {
+            return highlighting::State::Normal;
+        };
+
+        while *index < chars.len() {
+            let hl_type = highlighting::Type::MultilineComment;
+
+            if comment.nested && starts_with_at(chars, *index, &comment.start) {
+                self.push_highlighting(index, comment.start.chars().count(), hl_type, chars.len());
+                depth = depth.saturating_add(1);
+            } else if starts_with_at(chars, *index, &comment.end) {
+                self.push_highlighting(index, comment.end.chars().count(), hl_type, chars.len());
+
+                if depth == 0 {
+                    return highlighting::State::Normal;
                 }
+                depth = depth.saturating_sub(1);
+            } else {
+                self.push_highlighting(index, 1, hl_type, chars.len());
             }
         }
 
-        false
+        highlighting::State::BlockComment { rule, depth }
     }
 
-    #[allow(clippy::integer_arithmetic)]
     fn highlight_string(
         &mut self,
         index: &mut usize,
         opts: &HighlightingOptions,
-        c: char,
         chars: &[char],
-    ) -> bool {
+    ) -> Option<highlighting::State> {
         // TODO: Extend for <lib> in C/C++
-        if opts.strings() && c == '"' {
-            loop {
-                self.highlighting.push(highlighting::Type::String);
-                *index += 1;
+        let after_separator = *index == 0
+            || chars
+                .get(index.saturating_sub(1))
+                .is_none_or(|c| is_separator(*c));
+
+        let (rule, delimiter, hashes) = opts
+            .strings()
+            .iter()
+            .enumerate()
+            .filter_map(|(rule, delimiter)| {
+                let starts_word = delimiter.start.starts_with(char::is_alphanumeric);
+                if !starts_with_at(chars, *index, &delimiter.start)
+                    || (starts_word && !after_separator)
+                {
+                    return None;
+                }
 
-                if let Some(next_char) = chars.get(*index) {
-                    if *next_char == '"' {
-                        break;
+                let hashes = if delimiter.raw {
+                    let after_start = index.saturating_add(delimiter.start.chars().count());
+                    let hashes = chars
+                        .iter()
+                        .skip(after_start)
+                        .take_while(|c| **c == '#')
+                        .count();
+
+                    if !starts_with_at(chars, after_start.saturating_add(hashes), &delimiter.end) {
+                        return None;
                     }
+                    hashes
                 } else {
-                    break;
-                }
+                    0
+                };
+
+                Some((rule, delimiter, hashes))
+            })
+            .max_by_key(|(_, delimiter, hashes)| delimiter.start.len().saturating_add(*hashes))?;
+
+        let opening_len = if delimiter.raw {
+            delimiter
+                .start
+                .chars()
+                .count()
+                .saturating_add(hashes)
+                .saturating_add(delimiter.end.chars().count())
+        } else {
+            delimiter.start.chars().count()
+        };
+        self.push_highlighting(index, opening_len, highlighting::Type::String, chars.len());
+
+        Some(self.continue_string(index, opts, chars, rule, hashes))
+    }
+
+    fn continue_string(
+        &mut self,
+        index: &mut usize,
+        opts: &HighlightingOptions,
+        chars: &[char],
+        rule: usize,
+        hashes: usize,
+    ) -> highlighting::State {
+        let Some(delimiter) = opts.strings().get(rule) else {
+            return highlighting::State::Normal;
+        };
+        let closing = format!("{}{}", delimiter.end, "#".repeat(hashes));
+
+        while *index < chars.len() {
+            let hl_type = highlighting::Type::String;
+
+            if starts_with_at(chars, *index, &delimiter.escape) {
+                // The escaped character is consumed along with the escape
+                let len = delimiter.escape.chars().count().saturating_add(1);
+                self.push_highlighting(index, len, highlighting::Type::Escape, chars.len());
+            } else if starts_with_at(chars, *index, &closing) {
+                self.push_highlighting(index, closing.chars().count(), hl_type, chars.len());
+                return highlighting::State::Normal;
+            } else {
+                self.push_highlighting(index, 1, hl_type, chars.len());
             }
-            self.highlighting.push(highlighting::Type::String);
-            *index += 1;
+        }
 
-            return true;
+        if delimiter.multiline {
+            highlighting::State::String { rule, hashes }
+        } else {
+            highlighting::State::Normal
         }
+    }
 
-        false
+    /// Stops at `row_len`, the end of the row.
+    fn push_highlighting(
+        &mut self,
+        index: &mut usize,
+        len: usize,
+        hl_type: highlighting::Type,
+        row_len: usize,
+    ) {
+        let end = index.saturating_add(len).min(row_len);
+
+        while *index < end {
+            self.highlighting.push(hl_type);
+            *index = index.saturating_add(1);
+        }
     }
 
-    #[allow(clippy::integer_arithmetic)]
     fn highlight_number(
         &mut self,
         index: &mut usize,
         opts: &HighlightingOptions,
-        c: char,
         chars: &[char],
     ) -> bool {
-        if opts.numbers() && c.is_ascii_digit() {
-            if *index > 0 {
-                #[allow(clippy::indexing_slicing, clippy::integer_arithmetic)]
-                let prev_char = chars[*index - 1];
-                if !is_separator(prev_char) {
-                    return false;
-                }
-            }
+        if !opts.numbers().enabled {
+            return false;
+        }
 
-            loop {
-                self.highlighting.push(highlighting::Type::Number);
-                *index += 1;
-                if let Some(next_char) = chars.get(*index) {
-                    if *next_char != '.' && !next_char.is_ascii_digit() {
-                        break;
-                    }
-                } else {
-                    break;
-                }
+        let after_separator = *index == 0
+            || chars
+                .get(index.saturating_sub(1))
+                .is_none_or(|c| is_separator(*c));
+
+        match number_len(opts.numbers(), chars, *index) {
+            Some(len) if after_separator => {
+                self.push_highlighting(index, len, highlighting::Type::Number, chars.len());
+                true
+            },
+            _ => false,
+        }
+    }
+
+    fn highlight_attribute(
+        &mut self,
+        index: &mut usize,
+        opts: &HighlightingOptions,
+        chars: &[char],
+    ) -> bool {
+        let Some(prefix) = opts
+            .attributes()
+            .iter()
+            .filter(|prefix| starts_with_at(chars, *index, prefix))
+            .max_by_key(|prefix| prefix.len())
+        else {
+            return false;
+        };
+
+        let start = index.saturating_add(prefix.chars().count());
+        let len = if prefix.ends_with('[') {
+            let mut depth = 0_usize;
+            let end = chars
+                .iter()
+                .enumerate()
+                .skip(start)
+                .find(|(_, c)| match c {
+                    '[' => {
+                        depth = depth.saturating_add(1);
+                        false
+                    },
+                    ']' if depth == 0 => true,
+                    ']' => {
+                        depth = depth.saturating_sub(1);
+                        false
+                    },
+                    _ => false,
+                })
+                .map_or(chars.len(), |(end, _)| end.saturating_add(1));
+
+            end.saturating_sub(*index)
+        } else {
+            let name_len = chars
+                .iter()
+                .skip(start)
+                .take_while(|c| is_identifier_char(**c) || **c == '.')
+                .count();
+            if name_len == 0 {
+                return false;
             }
-            return true;
+
+            start.saturating_add(name_len).saturating_sub(*index)
+        };
+
+        self.push_highlighting(index, len, highlighting::Type::Attribute, chars.len());
+        true
+    }
+
+    #[allow(clippy::integer_arithmetic)]
+    fn highlight_lifetime(
+        &mut self,
+        index: &mut usize,
+        opts: &HighlightingOptions,
+        c: char,
+        chars: &[char],
+    ) -> bool {
+        if !opts.lifetimes() || c != '\'' {
+            return false;
         }
 
-        false
+        let name_len = chars
+            .iter()
+            .skip(*index + 1)
+            .take_while(|c| is_identifier_char(**c))
+            .count();
+        if name_len == 0 || chars.get(*index + 1).is_some_and(char::is_ascii_digit) {
+            return false;
+        }
+
+        self.push_highlighting(
+            index,
+            name_len + 1,
+            highlighting::Type::Lifetime,
+            chars.len(),
+        );
+        true
     }
 
-    #[allow(
-        clippy::indexing_slicing,
-        clippy::string_slice,
-        clippy::integer_arithmetic
-    )]
-    pub fn highlight(
+    #[allow(clippy::integer_arithmetic)]
+    fn highlight_identifier(
         &mut self,
+        index: &mut usize,
         opts: &HighlightingOptions,
-        word: &Option<String>,
-        start_with_comment: bool,
+        chars: &[char],
     ) -> bool {
-        let chars: Vec<char> = self.string.chars().collect();
-        let mut index = 0;
-        let mut in_ml_comment = start_with_comment;
+        let starts_identifier = chars
+            .get(*index)
+            .is_some_and(|c| c.is_alphabetic() || *c == '_');
+        let after_separator = *index == 0 || chars.get(*index - 1).is_none_or(|c| is_separator(*c));
+        if !starts_identifier || !after_separator {
+            return false;
+        }
 
-        if self.is_highlighted && word.is_none() {
-            if let Some(hl_type) = self.highlighting.last() {
-                if *hl_type == highlighting::Type::MultilineComment
-                    && self.string.len() > 1
-                    && self.string[self.string.len() - 2..] == *"*/"
-                {
-                    return true;
+        let len = chars
+            .iter()
+            .skip(*index)
+            .take_while(|c| is_identifier_char(**c))
+            .count();
+        let end = *index + len;
+        let macro_suffix = opts.macro_suffix();
+        let suffix_len = macro_suffix.chars().count();
+
+        if starts_with_at(chars, end, macro_suffix) && chars.get(end + suffix_len) != Some(&'=') {
+            self.push_highlighting(
+                index,
+                len + suffix_len,
+                highlighting::Type::Macro,
+                chars.len(),
+            );
+        } else if opts.functions() && chars.get(end) == Some(&'(') {
+            self.push_highlighting(index, len, highlighting::Type::Function, chars.len());
+        } else if opts.types() && is_camel_case(chars.get(*index..end).unwrap_or_default()) {
+            self.push_highlighting(index, len, highlighting::Type::TypeName, chars.len());
+        } else {
+            self.push_highlighting(index, len, highlighting::Type::None, chars.len());
+        }
+
+        true
+    }
+
+    fn highlight_todo_markers(&mut self, chars: &[char]) {
+        let is_comment = |hl_type: &highlighting::Type| {
+            matches!(
+                hl_type,
+                highlighting::Type::Comment | highlighting::Type::MultilineComment
+            )
+        };
+
+        let mut index = 0;
+        while index < chars.len() {
+            let len = chars
+                .iter()
+                .skip(index)
+                .take_while(|c| is_identifier_char(**c))
+                .count();
+            let word: String = chars.iter().skip(index).take(len).collect();
+
+            if TODO_MARKERS.contains(&word.as_str())
+                && self
+                    .highlighting
+                    .iter()
+                    .skip(index)
+                    .take(len)
+                    .all(is_comment)
+            {
+                for hl_type in self.highlighting.iter_mut().skip(index).take(len) {
+                    *hl_type = highlighting::Type::Todo;
                 }
             }
-            return false;
+
+            index = index.saturating_add(len.max(1));
         }
+    }
 
-        self.highlighting = Vec::new();
+    /// Turns one type per char into one type per grapheme, taking the type of its first char.
+    fn per_grapheme(&self, types: &[highlighting::Type]) -> Vec<highlighting::Type> {
+        let mut char_index = 0_usize;
 
-        if in_ml_comment {
-            let closing_index = if let Some(closing_index) = self.string.find("*/") {
-                closing_index + 2
-            } else {
-                chars.len()
-            };
+        self.string
+            .graphemes(true)
+            .map(|grapheme| {
+                let hl_type = types
+                    .get(char_index)
+                    .copied()
+                    .unwrap_or(highlighting::Type::None);
+                char_index = char_index.saturating_add(grapheme.chars().count());
+                hl_type
+            })
+            .collect()
+    }
 
-            for _ in 0..closing_index {
-                self.highlighting.push(highlighting::Type::MultilineComment);
-            }
+    pub fn end_state(&self) -> highlighting::State {
+        self.end_state
+    }
 
-            index = closing_index;
+    /// Takes highlighting computed elsewhere, such as by a syntax tree, as one type per char.
+    pub fn set_highlighting(
+        &mut self,
+        types: &[highlighting::Type],
+        word: &Option<String>,
+        starts_in_literal: bool,
+    ) {
+        self.highlighting = self.per_grapheme(types);
+        self.highlight_match(word);
+        self.start_state = highlighting::State::Normal;
+        self.end_state = highlighting::State::Normal;
+        self.starts_in_literal = starts_in_literal;
+        self.is_highlighted = true;
+    }
+
+    /// Highlights the row starting in `state`, returning the state the row ends in. Nothing is
+    /// done if the row is already highlighted starting in that same state.
+    #[allow(clippy::integer_arithmetic)]
+    pub fn highlight(
+        &mut self,
+        opts: &HighlightingOptions,
+        word: &Option<String>,
+        state: highlighting::State,
+    ) -> highlighting::State {
+        if self.is_highlighted && self.start_state == state {
+            return self.end_state;
         }
 
+        let chars: Vec<char> = self.string.chars().collect();
+        let mut index = 0;
+        self.highlighting = Vec::new();
+        self.start_state = state;
+        self.starts_in_literal = state != highlighting::State::Normal;
+
+        let mut state = match state {
+            highlighting::State::Normal => highlighting::State::Normal,
+            highlighting::State::BlockComment { rule, depth } => {
+                self.continue_multiline_comment(&mut index, opts, &chars, rule, depth)
+            },
+            highlighting::State::String { rule, hashes } => {
+                self.continue_string(&mut index, opts, &chars, rule, hashes)
+            },
+        };
+
         while let Some(c) = chars.get(index) {
-            if self.highlight_multiline_comment(&mut index, opts, *c, &chars) {
-                in_ml_comment = true;
+            if let Some(next_state) = self
+                .highlight_multiline_comment(&mut index, opts, &chars)
+                .or_else(|| self.highlight_string(&mut index, opts, &chars))
+            {
+                state = next_state;
                 continue;
             }
-            in_ml_comment = false;
 
             if self.highlight_char(&mut index, opts, *c, &chars)
-                || self.highlight_comment(&mut index, opts, *c, &chars)
+                || self.highlight_comment(&mut index, opts, &chars)
+                || self.highlight_attribute(&mut index, opts, &chars)
+                || self.highlight_lifetime(&mut index, opts, *c, &chars)
                 || self.highlight_primary_keywords(&mut index, opts, &chars)
                 || self.highlight_secondary_keywords(&mut index, opts, &chars)
-                || self.highlight_string(&mut index, opts, *c, &chars)
-                || self.highlight_number(&mut index, opts, *c, &chars)
+                || self.highlight_number(&mut index, opts, &chars)
+                || self.highlight_identifier(&mut index, opts, &chars)
             {
                 continue;
             }
-            self.highlighting.push(highlighting::Type::None);
+
+            let hl_type = if opts.operators().contains(*c) {
+                highlighting::Type::Operator
+            } else if opts.punctuation().contains(*c) {
+                highlighting::Type::Punctuation
+            } else {
+                highlighting::Type::None
+            };
+            self.highlighting.push(hl_type);
             index += 1;
         }
 
+        self.highlight_todo_markers(&chars);
+        self.highlighting = self.per_grapheme(&self.highlighting);
         self.highlight_match(word);
-        if in_ml_comment && &self.string[self.string.len().saturating_sub(2)..] != "*/" {
-            return true;
+        self.end_state = state;
+        self.is_highlighted = true;
+
+        state
+    }
+}
+
+fn starts_with_at(chars: &[char], index: usize, token: &str) -> bool {
+    !token.is_empty()
+        && token
+            .chars()
+            .enumerate()
+            .all(|(offset, c)| chars.get(index.saturating_add(offset)) == Some(&c))
+}
+
+#[allow(clippy::integer_arithmetic)]
+fn number_len(numbers: &Numbers, chars: &[char], start: usize) -> Option<usize> {
+    // Separators are only allowed between digits, and leading only where `leading` is set
+    let digits = |index: &mut usize, radix: u32, leading: bool| {
+        let from = *index;
+        let mut count = 0;
+        while let Some(c) = chars.get(*index) {
+            if c.is_digit(radix) {
+                count += 1;
+            } else if Some(*c) != numbers.separator || (*index == from && !leading) {
+                break;
+            }
+            *index += 1;
+        }
+        count
+    };
+
+    let prefixed = numbers
+        .prefixes
+        .iter()
+        .find(|(prefix, _)| starts_with_at_ignore_case(chars, start, prefix))
+        .and_then(|(prefix, radix)| {
+            let mut index = start + prefix.chars().count();
+            (digits(&mut index, *radix, numbers.separator_after_prefix) > 0).then_some(index)
+        });
+
+    let mut index = if let Some(index) = prefixed {
+        index
+    } else {
+        let mut index = start;
+        if digits(&mut index, 10, false) == 0 {
+            return None;
         }
 
-        self.is_highlighted = true;
-        false
+        if numbers.fractions
+            && chars.get(index) == Some(&'.')
+            && chars.get(index + 1).is_some_and(char::is_ascii_digit)
+        {
+            index += 1;
+            digits(&mut index, 10, false);
+        }
+
+        if chars
+            .get(index)
+            .is_some_and(|c| numbers.exponents.contains(*c))
+        {
+            let mut exponent = index + 1;
+            if matches!(chars.get(exponent), Some('+' | '-')) {
+                exponent += 1;
+            }
+            if digits(&mut exponent, 10, false) > 0 {
+                index = exponent;
+            }
+        }
+
+        index
+    };
+
+    if let Some(suffix) = numbers
+        .suffixes
+        .iter()
+        .filter(|suffix| starts_with_at_ignore_case(chars, index, suffix))
+        .max_by_key(|suffix| suffix.len())
+    {
+        index += suffix.chars().count();
+    }
+
+    Some(index - start)
+}
+
+fn starts_with_at_ignore_case(chars: &[char], index: usize, token: &str) -> bool {
+    !token.is_empty()
+        && token.chars().enumerate().all(|(offset, c)| {
+            chars
+                .get(index.saturating_add(offset))
+                .is_some_and(|other| other.eq_ignore_ascii_case(&c))
+        })
+}
+
+/// The number of columns `grapheme` takes up when drawn at `column`.
+fn grapheme_width(grapheme: &str, column: usize, tab_width: usize) -> usize {
+    if grapheme == "\t" {
+        let tab_width = tab_width.max(1);
+
+        #[allow(clippy::integer_arithmetic)]
+        return tab_width - column % tab_width;
     }
+
+    grapheme.width()
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Whether `identifier` starts with an uppercase letter and has lowercase ones, like `HashMap`
+/// but unlike `MAX_LEN`.
+fn is_camel_case(identifier: &[char]) -> bool {
+    identifier.first().is_some_and(|c| c.is_uppercase())
+        && identifier.iter().any(|c| c.is_lowercase())
 }
 
 fn is_separator(c: char) -> bool {
     c.is_ascii_punctuation() || c.is_ascii_whitespace()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filetype::Languages;
+
+    /// One letter per grapheme for the type it is highlighted with, blank for unhighlighted
+    /// whitespace.
+    fn code(hl_type: highlighting::Type, grapheme: &str) -> char {
+        match hl_type {
+            highlighting::Type::None if grapheme.trim().is_empty() => ' ',
+            highlighting::Type::None => '.',
+            highlighting::Type::Number => 'n',
+            highlighting::Type::Match => '?',
+            highlighting::Type::String => 's',
+            highlighting::Type::Char => 'c',
+            highlighting::Type::Comment => '/',
+            highlighting::Type::MultilineComment => '*',
+            highlighting::Type::PrimaryKeywords => 'K',
+            highlighting::Type::SecondaryKeywords => 'k',
+            highlighting::Type::Function => 'f',
+            highlighting::Type::TypeName => 't',
+            highlighting::Type::Macro => 'm',
+            highlighting::Type::Attribute => '@',
+            highlighting::Type::Lifetime => 'l',
+            highlighting::Type::Operator => 'o',
+            highlighting::Type::Punctuation => 'p',
+            highlighting::Type::Escape => 'e',
+            highlighting::Type::Todo => '!',
+        }
+    }
+
+    /// Highlights `lines` one after the other as `language`, returning their codes.
+    fn highlight(language: &str, lines: &[&str]) -> Vec<String> {
+        let file_type = Languages::default()
+            .find(language)
+            .expect("unknown language");
+        let mut state = highlighting::State::Normal;
+
+        lines
+            .iter()
+            .map(|line| {
+                let mut row = Row::from(*line);
+                state = row.highlight(file_type.highlighting_options(), &None, state);
+                row.string
+                    .graphemes(true)
+                    .zip(&row.highlighting)
+                    .map(|(grapheme, hl_type)| code(*hl_type, grapheme))
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Asserts the codes of each line, highlighted one after the other as `language`.
+    fn check(language: &str, expected: &[(&str, &str)]) {
+        let lines: Vec<&str> = expected.iter().map(|(line, _)| *line).collect();
+        let codes: Vec<&str> = expected.iter().map(|(_, codes)| *codes).collect();
+
+        assert_eq!(highlight(language, &lines), codes);
+    }
+
+    #[test]
+    fn highlights_python() {
+        check(
+            "Python",
+            &[
+                ("@cache", "@@@@@@"),
+                (
+                    "def area(r: float) -> Shape:",
+                    "KKK ffffp.p kkkkkp oo tttttp",
+                ),
+                ("    \"\"\"TODO: doc", "    ssssssssssss"),
+                ("    more\"\"\"", "sssssssssss"),
+                (
+                    "    return 3.14 * r ** 2  # NOTE: pi",
+                    "    KKKKKK nnnn o . oo n  //!!!!////",
+                ),
+                (
+                    "x = f'{a}\\n' + r'\\d' + b\"x\"",
+                    ". o sssssees o sssss o ssss",
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn highlights_go() {
+        check(
+            "Go",
+            &[
+                ("func main() {", "KKKK ffffpp p"),
+                ("\tvar s string = `raw", " KKK . kkkkkk o ssss"),
+                ("still raw` // done", "ssssssssss ///////"),
+                ("\tx := 0x1F + 'a' + len(s)", " . po nnnn o ccc o fffp.p"),
+                ("}", "p"),
+            ],
+        );
+    }
+
+    #[test]
+    fn highlights_typescript() {
+        check(
+            "TypeScript",
+            &[
+                (
+                    "export const f = async (x: number): Promise<void> => {",
+                    "KKKKKK KKKKK . o KKKKK p.p kkkkkkpp kkkkkkkoKKKKo oo p",
+                ),
+                ("  const t = `a ${x}", "  KKKKK . o sssssss"),
+                ("  b`; /* multi", "ssssp ********"),
+                ("  line */ return 1_000n;", "********* KKKKKK nnnnnnp"),
+            ],
+        );
+    }
+
+    #[test]
+    fn highlights_json() {
+        check(
+            "JSON",
+            &[
+                (
+                    "{\"a\": [1, -2.5e3, true, null],",
+                    "psssp pnp .nnnnnp KKKKp KKKKpp",
+                ),
+                (" \"b\\\"\": \"x\" // c", " sseesp sss ////"),
+                ("}", "p"),
+            ],
+        );
+    }
+
+    #[test]
+    fn highlights_yaml() {
+        check(
+            "YAML",
+            &[
+                ("key: 'it''s' # FIXME", ".... sssssss //!!!!!"),
+                ("n: 0x1F", ".. nnnn"),
+                ("s: \"a\\tb\"", ".. sseess"),
+            ],
+        );
+    }
+
+    #[test]
+    fn highlights_toml() {
+        check(
+            "TOML",
+            &[
+                ("[package]", "........."),
+                ("name = \"\"\"multi", ".... . ssssssss"),
+                ("line\"\"\" # x", "sssssss ///"),
+                ("n = 1_000 # TODO", ". . nnnnn //!!!!"),
+                ("p = 'C:\\path'", ". . sssssssss"),
+            ],
+        );
+    }
+
+    #[test]
+    fn highlights_markdown() {
+        check(
+            "Markdown",
+            &[
+                ("# Title <!-- c", ". ..... ******"),
+                ("still --> `code` 42", "********* ssssss .."),
+                ("```rust", "sssssss"),
+                ("let x = 1;", "ssssssssss"),
+                ("```", "sss"),
+            ],
+        );
+    }
+
+    #[test]
+    fn highlights_shell() {
+        check(
+            "Shell",
+            &[
+                ("if [ -f \"$HOME/x\" ]; then", "KK p .. sssssssss pp KKKK"),
+                ("  echo 'multi", "  kkkk ssssss"),
+                ("  line' # end", "sssssss /////"),
+                ("fi", "KK"),
+            ],
+        );
+    }
+
+    #[test]
+    fn highlights_sql() {
+        check(
+            "SQL",
+            &[
+                ("SELECT count(*) FROM t -- c", "KKKKKK fffffpop KKKK . ////"),
+                ("where a = 'it''s' /* b", "KKKKK . o sssssss ****"),
+                ("*/ AND b = 1.5;", "** KKK . o nnnp"),
+            ],
+        );
+    }
+
+    #[test]
+    fn highlights_c() {
+        check(
+            "C",
+            &[
+                ("#include <stdio.h>", ".KKKKKKK o.....p.o"),
+                ("int main(void) { /* TODO", "kkk ffffpkkkkp p ***!!!!"),
+                (
+                    "   */ return 0x1Fu + 'c' + '\\n'; // x",
+                    "***** KKKKKK nnnnn o ccc o ccccp ////",
+                ),
+                ("}", "p"),
+            ],
+        );
+    }
+
+    #[test]
+    fn highlights_haskell() {
+        check(
+            "Haskell",
+            &[
+                ("{- outer {- inner -} still", "**************************"),
+                ("   comment -} main :: IO ()", "************* .... oo .. pp"),
+                ("main = do -- hi", ".... o KK /////"),
+                (
+                    "  let xs = foldl' (+) 0 [1, 0x_FF]",
+                    "  KKK .. o kkkkk. pop n pnp nnnnnp",
+                ),
+                (
+                    "  putStrLn \"a\\\"b\" >> print 'c'",
+                    "  kkkkkkkk sseess oo kkkkk ccc",
+                ),
+                ("{-# LANGUAGE GADTs #-}", "**********************"),
+            ],
+        );
+    }
+
+    #[test]
+    fn highlights_lua() {
+        check(
+            "Lua",
+            &[
+                (
+                    "local function f(t) --[[ block",
+                    "KKKKK KKKKKKKK fp.p **********",
+                ),
+                ("  ]] return #t .. [[long", "**** KKKKKK o. oo ssssss"),
+                ("string]] end -- TODO", "ssssssss KKK ///!!!!"),
+                ("print(0xFF, 1e3, 'x')", "kkkkkpnnnnp nnnp sssp"),
+            ],
+        );
+    }
+
+    #[test]
+    fn highlights_numbers() {
+        check(
+            "Rust",
+            &[
+                ("0xFF 0b1010 1_000_000", "nnnn nnnnnn nnnnnnnnn"),
+                ("1e-9 3.0f32 10u64 0x_1F", "nnnn nnnnnn nnnnn nnnnn"),
+                ("1.max(2) 0x 1_", "npfffpnp n. nn"),
+            ],
+        );
+        check("Go", &[("0x_1F 0b_1 0x_", "nnnnn nnnn n..")]);
+        check("Python", &[("0x_1F 1_000j", "nnnnn nnnnnn")]);
+        check("TypeScript", &[("0x_1F 10n", "n..n. nnn")]);
+    }
+
+    /// Text that is easy to get wrong: combining marks, ZWJ emoji, wide CJK, tabs and carriage
+    /// returns, next to the tokens that start strings, comments and numbers.
+    const PIECES: [&str; 21] = [
+        "a",
+        "Z",
+        " ",
+        "\t",
+        "\r",
+        "e\u{301}",
+        "\u{301}",
+        "\u{200d}",
+        "👩\u{200d}💻",
+        "🇫🇷",
+        "漢",
+        "字",
+        "\"",
+        "'",
+        "`",
+        "/*",
+        "*/",
+        "//",
+        "#",
+        "0x_1F",
+        "1e-9",
+    ];
+
+    /// A xorshift generator, so that a failing case comes back on every run.
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            usize::try_from(self.0 % n.max(1) as u64).unwrap_or_default()
+        }
+
+        fn piece(&mut self) -> &'static str {
+            PIECES[self.below(PIECES.len())]
+        }
+
+        fn text(&mut self, max: usize) -> String {
+            (0..self.below(max)).map(|_| self.piece()).collect()
+        }
+    }
+
+    fn assert_in_sync(row: &Row) {
+        assert_eq!(
+            row.len(),
+            row.as_str().graphemes(true).count(),
+            "{:?}",
+            row.as_str()
+        );
+    }
+
+    /// Drops the escape sequences `render` styles the text with.
+    fn strip_styles(rendered: &str) -> String {
+        let mut text = String::new();
+        let mut chars = rendered.chars();
+        while let Some(c) = chars.next() {
+            if c == '\x1b' {
+                chars.by_ref().find(char::is_ascii_alphabetic);
+            } else {
+                text.push(c);
+            }
+        }
+        text
+    }
+
+    fn width_of(text: &str) -> usize {
+        text.graphemes(true).map(UnicodeWidthStr::width).sum()
+    }
+
+    #[test]
+    fn arbitrary_edits_keep_graphemes_in_sync() {
+        let languages = Languages::default();
+        let file_types: Vec<_> = ["Rust", "Python", "C", "Markdown", "Shell"]
+            .iter()
+            .filter_map(|name| languages.find(name))
+            .collect();
+        let theme = Theme::default();
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+
+        for _ in 0..2000 {
+            let mut row = Row::from(rng.text(8).as_str());
+            let opts = file_types[rng.below(file_types.len())].highlighting_options();
+
+            for _ in 0..12 {
+                // Past the end of the row as well
+                let at = rng.below(row.len() + 2);
+                match rng.below(5) {
+                    0 => {
+                        let piece = rng.piece();
+                        for c in piece.chars().rev() {
+                            row.insert(at, c);
+                        }
+                    },
+                    1 => row.delete(at),
+                    2 => {
+                        let text = row.as_str().to_owned();
+                        let tail = row.split(at);
+                        assert_in_sync(&row);
+                        assert_in_sync(&tail);
+                        assert_eq!(row.len(), at.min(text.graphemes(true).count()));
+                        row.append(&tail);
+                        assert_eq!(row.as_str(), text);
+                    },
+                    3 => row.append(&Row::from(rng.text(4).as_str())),
+                    _ => {
+                        row.trim_end();
+                    },
+                }
+                assert_in_sync(&row);
+
+                let word = (rng.below(3) == 0).then(|| rng.piece().to_owned());
+                // Carrying on from the state the row ended in covers multi-line literals
+                let state = if rng.below(2) == 0 {
+                    row.end_state()
+                } else {
+                    highlighting::State::Normal
+                };
+                row.is_highlighted = false;
+                row.highlight(opts, &word, state);
+                assert_eq!(row.highlighting.len(), row.len(), "{:?}", row.as_str());
+
+                let tab_width = rng.below(9);
+                let width = rng.below(12);
+                let start = rng.below(row.column(row.len(), tab_width) + 2);
+                let selection = rng.below(row.len() + 1)..rng.below(row.len() + 1);
+                let rendered =
+                    row.render(start..start + width, tab_width, &theme, &[at], &selection);
+                let row_width = row.column(row.len(), tab_width);
+                assert_eq!(
+                    width_of(&strip_styles(&rendered)),
+                    row_width.min(start + width).saturating_sub(start),
+                    "{:?} from {start}, {width} wide",
+                    row.as_str()
+                );
+
+                let points = row.wrap_points(width, tab_width);
+                assert!(points.windows(2).all(|pair| pair[0] < pair[1]));
+                assert!(points.iter().all(|point| *point <= row.len()));
+
+                let direction = if rng.below(2) == 0 {
+                    SearchDirection::Forward
+                } else {
+                    SearchDirection::Backward
+                };
+                let query = rng.piece();
+                let text = row.as_str();
+                let end = row.byte_index(at.min(row.len()));
+                let candidates: Vec<usize> = text
+                    .grapheme_indices(true)
+                    .enumerate()
+                    .filter(|&(_, (byte, _))| {
+                        at <= row.len()
+                            && match direction {
+                                SearchDirection::Forward => byte >= end,
+                                SearchDirection::Backward => byte + query.len() <= end,
+                            }
+                    })
+                    .filter(|&(_, (byte, _))| text[byte..].starts_with(query))
+                    .map(|(index, _)| index)
+                    .collect();
+                let expected = match direction {
+                    SearchDirection::Forward => candidates.first(),
+                    SearchDirection::Backward => candidates.last(),
+                };
+                assert_eq!(
+                    row.find(query, at, direction),
+                    expected.copied(),
+                    "{query:?} in {text:?} from {at}, {direction:?}"
+                );
+            }
+        }
+    }
+}
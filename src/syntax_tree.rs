@@ -0,0 +1,306 @@
+#[cfg(not(feature = "tree-sitter"))]
+use std::ops::Range;
+
+#[cfg(not(feature = "tree-sitter"))]
+use crate::Row;
+
+/// A change to the document text, in bytes and `(row, byte column)` points, where every row is
+/// followed by a newline.
+#[cfg_attr(not(feature = "tree-sitter"), allow(dead_code))]
+pub struct TextEdit {
+    pub start_byte: usize,
+    pub old_end_byte: usize,
+    pub new_end_byte: usize,
+    pub start: (usize, usize),
+    pub old_end: (usize, usize),
+    pub new_end: (usize, usize),
+}
+
+#[cfg(feature = "tree-sitter")]
+pub use self::tree_sitter_backend::SyntaxTree;
+
+#[cfg(not(feature = "tree-sitter"))]
+pub struct SyntaxTree;
+
+/// Stand-in for builds without the `tree-sitter` feature, which never has a grammar.
+#[cfg(not(feature = "tree-sitter"))]
+#[allow(clippy::unused_self)]
+impl SyntaxTree {
+    pub fn for_file_type(_name: &str) -> Option<Self> {
+        None
+    }
+
+    pub fn edit(&mut self, _edit: &TextEdit) {}
+
+    pub fn invalidate(&mut self) {}
+
+    pub fn highlight(&mut self, _rows: &mut [Row], _visible: Range<usize>, _word: &Option<String>) {
+    }
+}
+
+#[cfg(feature = "tree-sitter")]
+mod tree_sitter_backend {
+    use std::ops::Range;
+
+    use tree_sitter::{InputEdit, Language, Node, Parser, Point, Query, QueryCursor, Tree};
+
+    use super::TextEdit;
+    use crate::{highlighting, Row};
+
+    pub struct SyntaxTree {
+        parser: Parser,
+        tree: Option<Tree>,
+        edited: bool,
+        query: Query,
+        capture_types: Vec<highlighting::Type>,
+    }
+
+    fn grammar(name: &str) -> Option<(Language, String)> {
+        match name {
+            "Rust" => Some((
+                tree_sitter_rust::language(),
+                tree_sitter_rust::HIGHLIGHT_QUERY.to_owned(),
+            )),
+            "C" => Some((
+                tree_sitter_c::language(),
+                tree_sitter_c::HIGHLIGHT_QUERY.to_owned(),
+            )),
+            // The C++ query only covers what C++ adds on top of C
+            "C++" => Some((
+                tree_sitter_cpp::language(),
+                format!(
+                    "{}\n{}",
+                    tree_sitter_cpp::HIGHLIGHT_QUERY,
+                    tree_sitter_c::HIGHLIGHT_QUERY
+                ),
+            )),
+            _ => None,
+        }
+    }
+
+    fn capture_type(capture_name: &str) -> highlighting::Type {
+        match capture_name {
+            "function.macro" => return highlighting::Type::Macro,
+            "type.builtin" => return highlighting::Type::SecondaryKeywords,
+            _ => (),
+        }
+
+        match capture_name.split('.').next().unwrap_or_default() {
+            "keyword" => highlighting::Type::PrimaryKeywords,
+            "type" | "constructor" => highlighting::Type::TypeName,
+            "string" => highlighting::Type::String,
+            "escape" => highlighting::Type::Escape,
+            "comment" => highlighting::Type::Comment,
+            "function" => highlighting::Type::Function,
+            "attribute" => highlighting::Type::Attribute,
+            "label" => highlighting::Type::Lifetime,
+            "operator" => highlighting::Type::Operator,
+            "punctuation" => highlighting::Type::Punctuation,
+            "number" | "constant" if capture_name != "constant" => highlighting::Type::Number,
+            _ => highlighting::Type::None,
+        }
+    }
+
+    impl SyntaxTree {
+        /// Returns `None` when no grammar is compiled in for the file type.
+        pub fn for_file_type(name: &str) -> Option<Self> {
+            let (language, query) = grammar(name)?;
+            let mut parser = Parser::new();
+            parser.set_language(language).ok()?;
+            let query = Query::new(language, &query).ok()?;
+            let capture_types = query
+                .capture_names()
+                .iter()
+                .map(|name| capture_type(name))
+                .collect();
+
+            Some(Self {
+                parser,
+                tree: None,
+                edited: false,
+                query,
+                capture_types,
+            })
+        }
+
+        pub fn edit(&mut self, edit: &TextEdit) {
+            let point = |(row, column)| Point { row, column };
+
+            self.edited = true;
+            if let Some(tree) = &mut self.tree {
+                tree.edit(&InputEdit {
+                    start_byte: edit.start_byte,
+                    old_end_byte: edit.old_end_byte,
+                    new_end_byte: edit.new_end_byte,
+                    start_position: point(edit.start),
+                    old_end_position: point(edit.old_end),
+                    new_end_position: point(edit.new_end),
+                });
+            }
+        }
+
+        pub fn invalidate(&mut self) {
+            self.tree = None;
+        }
+
+        fn parse(&mut self, rows: &[Row]) {
+            if self.tree.is_some() && !self.edited {
+                return;
+            }
+
+            let mut read = |_, point: Point| -> &[u8] {
+                match rows.get(point.row) {
+                    Some(row) => match row.as_bytes().get(point.column..) {
+                        Some(rest) if !rest.is_empty() => rest,
+                        _ => b"\n",
+                    },
+                    None => &[],
+                }
+            };
+
+            // The edited old tree lets tree-sitter reuse everything the edits did not touch
+            self.tree = self.parser.parse_with(&mut read, self.tree.as_ref());
+            self.edited = false;
+        }
+
+        #[allow(clippy::integer_arithmetic)]
+        pub fn highlight(
+            &mut self,
+            rows: &mut [Row],
+            visible: Range<usize>,
+            word: &Option<String>,
+        ) {
+            self.parse(rows);
+            let Some(tree) = &self.tree else {
+                return;
+            };
+
+            let visible = visible.start..visible.end.min(rows.len());
+            if visible.is_empty() {
+                return;
+            }
+
+            let mut spans = Vec::new();
+            {
+                let rows: &[Row] = rows;
+                let text = |node: Node| node_text(rows, node).into_iter();
+                let mut cursor = QueryCursor::new();
+                cursor.set_point_range(
+                    Point {
+                        row: visible.start,
+                        column: 0,
+                    }..Point {
+                        row: visible.end,
+                        column: 0,
+                    },
+                );
+
+                for (query_match, index) in cursor.captures(&self.query, tree.root_node(), text) {
+                    let Some(capture) = query_match.captures.get(index) else {
+                        continue;
+                    };
+                    let hl_type = self
+                        .capture_types
+                        .get(capture.index as usize)
+                        .copied()
+                        .unwrap_or(highlighting::Type::None);
+
+                    spans.push((
+                        capture.node.start_position(),
+                        capture.node.end_position(),
+                        capture.node.byte_range().len(),
+                        query_match.pattern_index,
+                        hl_type,
+                    ));
+                }
+            }
+
+            // Outer nodes are painted first so that nested ones win, while for the same node
+            // the earliest pattern in the query wins
+            spans.sort_by_key(|(start, _, len, pattern, _)| {
+                (start.row, start.column, usize::MAX - len, *pattern)
+            });
+            spans.dedup_by_key(|(start, end, _, _, _)| (*start, *end));
+
+            // Rows starting inside a string or a comment that began on an earlier row
+            let mut continued = vec![false; visible.len()];
+            let mut highlighting: Vec<Vec<highlighting::Type>> = rows
+                .get(visible.clone())
+                .unwrap_or_default()
+                .iter()
+                .map(|row| vec![highlighting::Type::None; row.as_str().chars().count()])
+                .collect();
+
+            for (start, end, _, _, hl_type) in spans {
+                if hl_type == highlighting::Type::None {
+                    continue;
+                }
+
+                for y in start.row.max(visible.start)..=end.row.min(visible.end - 1) {
+                    let (Some(row), Some(types)) =
+                        (rows.get(y), highlighting.get_mut(y - visible.start))
+                    else {
+                        continue;
+                    };
+                    let from = if y == start.row {
+                        row.char_index(start.column)
+                    } else {
+                        if y < end.row || end.column > 0 {
+                            if let Some(continued) = continued.get_mut(y - visible.start) {
+                                *continued = hl_type.is_literal();
+                            }
+                        }
+                        0
+                    };
+                    let to = if y == end.row {
+                        row.char_index(end.column)
+                    } else {
+                        types.len()
+                    };
+
+                    for slot in types.iter_mut().take(to).skip(from) {
+                        *slot = hl_type;
+                    }
+                }
+            }
+
+            for ((row, types), continued) in rows
+                .get_mut(visible)
+                .unwrap_or_default()
+                .iter_mut()
+                .zip(highlighting)
+                .zip(continued)
+            {
+                row.set_highlighting(&types, word, continued);
+            }
+        }
+    }
+
+    fn node_text<'a>(rows: &'a [Row], node: Node) -> Vec<&'a [u8]> {
+        let start = node.start_position();
+        let end = node.end_position();
+        let mut slices = Vec::new();
+
+        for y in start.row..=end.row {
+            let Some(row) = rows.get(y) else {
+                break;
+            };
+            let bytes = row.as_bytes();
+            let from = if y == start.row { start.column } else { 0 };
+            let to = if y == end.row {
+                end.column
+            } else {
+                bytes.len()
+            };
+
+            if let Some(slice) = bytes.get(from..to.min(bytes.len())) {
+                slices.push(slice);
+            }
+            if y != end.row {
+                slices.push(b"\n");
+            }
+        }
+
+        slices
+    }
+}
@@ -1,13 +1,13 @@
 use std::io::{stdout, Write};
 
 use crossterm::cursor::{Hide, MoveTo, Show};
-use crossterm::event::{self, Event, KeyEvent};
-use crossterm::style::{self, Color};
+use crossterm::event::{self, DisableMouseCapture, EnableMouseCapture, Event};
 use crossterm::terminal::{enable_raw_mode, Clear, ClearType};
 use crossterm::{execute, Result};
 
 use crate::Position;
 
+/// The size of the screen left to the windows, which is all but the message bar.
 pub struct Size {
     pub width: u16,
     pub height: u16,
@@ -23,7 +23,7 @@ impl Terminal {
         Ok(Self {
             size: Size {
                 width: size.0,
-                height: size.1.saturating_sub(2),
+                height: size.1.saturating_sub(1),
             },
         })
     }
@@ -32,11 +32,12 @@ impl Terminal {
         &self.size
     }
 
+    /// Reads the size of the terminal again, keeping the last known one if that fails.
     pub fn update_size(&mut self) {
-        // TODO: Handle this?
-        let size = crossterm::terminal::size().unwrap();
-        self.size.width = size.0;
-        self.size.height = size.1.saturating_sub(2);
+        if let Ok((width, height)) = crossterm::terminal::size() {
+            self.size.width = width;
+            self.size.height = height.saturating_sub(1);
+        }
     }
 
     pub fn clear_screen() {
@@ -55,11 +56,16 @@ impl Terminal {
         stdout().flush()
     }
 
-    pub fn read_key() -> Result<KeyEvent> {
-        loop {
-            if let Event::Key(event) = event::read()? {
-                return Ok(event);
-            }
+    /// Waits for a key press, a mouse event or the terminal being resized.
+    pub fn read_event() -> Result<Event> {
+        event::read()
+    }
+
+    pub fn set_mouse_capture(enabled: bool) {
+        if enabled {
+            execute!(stdout(), EnableMouseCapture).ok();
+        } else {
+            execute!(stdout(), DisableMouseCapture).ok();
         }
     }
 
@@ -74,16 +80,4 @@ impl Terminal {
     pub fn clear_current_line() {
         execute!(stdout(), Clear(ClearType::CurrentLine)).ok();
     }
-
-    pub fn set_bg_color(color: Color) {
-        execute!(stdout(), style::SetBackgroundColor(color)).ok();
-    }
-
-    pub fn reset_color() {
-        execute!(stdout(), style::ResetColor).ok();
-    }
-
-    pub fn set_fg_color(color: Color) {
-        execute!(stdout(), style::SetForegroundColor(color)).ok();
-    }
 }
@@ -0,0 +1,365 @@
+use std::collections::HashMap;
+use std::env;
+use std::path::Path;
+
+use crossterm::style::{Attribute, Attributes, Color, ContentStyle};
+use serde::Deserialize;
+use tokio::fs;
+
+use crate::config::parse_color;
+use crate::highlighting;
+
+const BUILTIN_THEMES: [(&str, &str); 2] = [
+    ("default", include_str!("../themes/default.toml")),
+    ("gruvbox", include_str!("../themes/gruvbox.toml")),
+];
+
+#[derive(PartialEq, Eq, Hash, Clone, Copy)]
+pub enum UiElement {
+    StatusBar,
+    InactiveStatusBar,
+    WindowSeparator,
+    TabBar,
+    ActiveTab,
+    MessageBar,
+    LineNumbers,
+    CurrentLineNumber,
+    Selection,
+    SearchMatch,
+    MatchingBracket,
+    WrapIndicator,
+    Tildes,
+}
+
+impl UiElement {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "status_bar" => Some(Self::StatusBar),
+            "inactive_status_bar" => Some(Self::InactiveStatusBar),
+            "window_separator" => Some(Self::WindowSeparator),
+            "tab_bar" => Some(Self::TabBar),
+            "active_tab" => Some(Self::ActiveTab),
+            "message_bar" => Some(Self::MessageBar),
+            "line_numbers" => Some(Self::LineNumbers),
+            "current_line_number" => Some(Self::CurrentLineNumber),
+            "selection" => Some(Self::Selection),
+            "search_match" => Some(Self::SearchMatch),
+            "matching_bracket" => Some(Self::MatchingBracket),
+            "wrap_indicator" => Some(Self::WrapIndicator),
+            "tildes" => Some(Self::Tildes),
+            _ => None,
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Copy)]
+pub enum ColorDepth {
+    TrueColor,
+    Ansi256,
+    Ansi16,
+}
+
+impl ColorDepth {
+    /// Guesses what the terminal supports from `COLORTERM` and `TERM`.
+    pub fn detect() -> Self {
+        let colorterm = env::var("COLORTERM").unwrap_or_default();
+        let term = env::var("TERM").unwrap_or_default();
+
+        if colorterm == "truecolor" || colorterm == "24bit" {
+            Self::TrueColor
+        } else if term.contains("256color") {
+            Self::Ansi256
+        } else {
+            Self::Ansi16
+        }
+    }
+
+    fn adapt(self, color: Color) -> Color {
+        match (self, color) {
+            (Self::Ansi256 | Self::Ansi16, Color::Rgb { r, g, b }) => {
+                if self == Self::Ansi256 {
+                    Color::AnsiValue(rgb_to_ansi256(r, g, b))
+                } else {
+                    nearest_ansi16(r, g, b)
+                }
+            },
+            (Self::Ansi16, Color::AnsiValue(value)) if value >= 16 => {
+                let (r, g, b) = ansi256_to_rgb(value);
+                nearest_ansi16(r, g, b)
+            },
+            _ => color,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawStyle {
+    Fg(String),
+    Full {
+        #[serde(default)]
+        fg: Option<String>,
+        #[serde(default)]
+        bg: Option<String>,
+        #[serde(default)]
+        attributes: Vec<String>,
+    },
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct RawTheme {
+    syntax: HashMap<String, RawStyle>,
+    ui: HashMap<String, RawStyle>,
+}
+
+pub struct Theme {
+    name: String,
+    depth: ColorDepth,
+    syntax: HashMap<highlighting::Type, ContentStyle>,
+    ui: HashMap<UiElement, ContentStyle>,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        let mut theme = Self {
+            name: "default".to_owned(),
+            depth: ColorDepth::detect(),
+            syntax: HashMap::new(),
+            ui: HashMap::new(),
+        };
+        theme.apply(BUILTIN_THEMES[0].1, &mut Vec::new());
+
+        theme
+    }
+}
+
+impl Theme {
+    /// Loads `name` from the `themes` directory next to the config file, falling back to the
+    /// built-in themes. Problems are appended to `errors` and the default theme is kept.
+    pub async fn load(name: &str, config_dir: Option<&Path>, errors: &mut Vec<String>) -> Self {
+        let mut theme = Self::default();
+
+        let user_theme = match config_dir {
+            Some(dir) => fs::read_to_string(dir.join("themes").join(format!("{}.toml", name)))
+                .await
+                .ok(),
+            None => None,
+        };
+        let builtin_theme = BUILTIN_THEMES
+            .iter()
+            .find(|(builtin, _)| *builtin == name)
+            .map(|(_, contents)| (*contents).to_owned());
+
+        if let Some(contents) = user_theme.or(builtin_theme) {
+            theme.name = name.to_owned();
+            theme.apply(&contents, errors);
+        } else {
+            errors.push(format!("theme: unknown theme `{}`", name));
+        }
+
+        theme
+    }
+
+    pub fn syntax(&self, hl_type: highlighting::Type) -> ContentStyle {
+        if hl_type == highlighting::Type::Match {
+            return self.ui(UiElement::SearchMatch);
+        }
+
+        self.syntax.get(&hl_type).copied().unwrap_or_default()
+    }
+
+    pub fn ui(&self, element: UiElement) -> ContentStyle {
+        self.ui.get(&element).copied().unwrap_or_default()
+    }
+
+    /// Draws `element` over `style`, keeping the colors `element` leaves unset.
+    pub fn layer(&self, style: ContentStyle, element: UiElement) -> ContentStyle {
+        let top = self.ui(element);
+
+        ContentStyle {
+            foreground_color: top.foreground_color.or(style.foreground_color),
+            background_color: top.background_color.or(style.background_color),
+            attributes: style.attributes | top.attributes,
+        }
+    }
+
+    pub fn set_color(&mut self, name: &str, color: Color) -> bool {
+        let color = Some(self.depth.adapt(color));
+
+        if let Some(hl_type) = highlighting::Type::from_name(name) {
+            self.syntax.entry(hl_type).or_default().foreground_color = color;
+        } else if let Some(element) = UiElement::from_name(name) {
+            self.ui.entry(element).or_default().foreground_color = color;
+        } else if name == "match" {
+            self.ui
+                .entry(UiElement::SearchMatch)
+                .or_default()
+                .foreground_color = color;
+        } else if name == "status_fg" {
+            self.ui
+                .entry(UiElement::StatusBar)
+                .or_default()
+                .foreground_color = color;
+        } else if name == "status_bg" {
+            self.ui
+                .entry(UiElement::StatusBar)
+                .or_default()
+                .background_color = color;
+        } else {
+            return false;
+        }
+
+        true
+    }
+
+    fn apply(&mut self, contents: &str, errors: &mut Vec<String>) {
+        let raw: RawTheme = match toml::from_str(contents) {
+            Ok(raw) => raw,
+            Err(e) => {
+                errors.push(format!("theme `{}`: {}", self.name, e));
+                return;
+            },
+        };
+
+        for (name, style) in raw.syntax {
+            let style = self.parse_style(&name, style, errors);
+            if let Some(hl_type) = highlighting::Type::from_name(&name) {
+                self.syntax.insert(hl_type, style);
+            } else {
+                errors.push(format!(
+                    "theme `{}`: unknown token type `{}`",
+                    self.name, name
+                ));
+            }
+        }
+
+        for (name, style) in raw.ui {
+            let style = self.parse_style(&name, style, errors);
+            if let Some(element) = UiElement::from_name(&name) {
+                self.ui.insert(element, style);
+            } else {
+                errors.push(format!(
+                    "theme `{}`: unknown UI element `{}`",
+                    self.name, name
+                ));
+            }
+        }
+    }
+
+    fn parse_style(&self, name: &str, style: RawStyle, errors: &mut Vec<String>) -> ContentStyle {
+        let (fg, bg, attributes) = match style {
+            RawStyle::Fg(fg) => (Some(fg), None, Vec::new()),
+            RawStyle::Full { fg, bg, attributes } => (fg, bg, attributes),
+        };
+
+        let mut color = |value: Option<String>| {
+            let value = value?;
+            let color = parse_color(&value);
+            if color.is_none() {
+                errors.push(format!(
+                    "theme `{}`: invalid color `{}` for `{}`",
+                    self.name, value, name
+                ));
+            }
+            color.map(|color| self.depth.adapt(color))
+        };
+
+        let mut style = ContentStyle {
+            foreground_color: color(fg),
+            background_color: color(bg),
+            attributes: Attributes::default(),
+        };
+
+        for attribute in attributes {
+            match parse_attribute(&attribute) {
+                Some(attribute) => style.attributes.set(attribute),
+                None => errors.push(format!(
+                    "theme `{}`: invalid attribute `{}` for `{}`",
+                    self.name, attribute, name
+                )),
+            }
+        }
+
+        style
+    }
+}
+
+fn parse_attribute(name: &str) -> Option<Attribute> {
+    match name {
+        "bold" => Some(Attribute::Bold),
+        "dim" => Some(Attribute::Dim),
+        "italic" => Some(Attribute::Italic),
+        "underlined" | "underline" => Some(Attribute::Underlined),
+        "reverse" | "reversed" => Some(Attribute::Reverse),
+        "crossed_out" | "strikethrough" => Some(Attribute::CrossedOut),
+        _ => None,
+    }
+}
+
+#[allow(clippy::integer_arithmetic, clippy::integer_division)]
+fn rgb_to_ansi256(r: u8, g: u8, b: u8) -> u8 {
+    // Greys get a finer ramp than the 6x6x6 cube offers
+    if r == g && g == b {
+        return match r {
+            0..=7 => 16,
+            248..=255 => 231,
+            _ => 232 + (r - 8) / 10,
+        };
+    }
+
+    let cube = |channel: u8| if channel < 48 { 0 } else { (channel - 35) / 40 };
+    16 + 36 * cube(r) + 6 * cube(g) + cube(b)
+}
+
+#[allow(clippy::integer_arithmetic, clippy::integer_division)]
+fn ansi256_to_rgb(value: u8) -> (u8, u8, u8) {
+    match value {
+        16..=231 => {
+            let level = |index: u8| if index == 0 { 0 } else { 55 + index * 40 };
+            let index = value - 16;
+            (level(index / 36), level(index / 6 % 6), level(index % 6))
+        },
+        232..=255 => {
+            let grey = 8 + (value - 232) * 10;
+            (grey, grey, grey)
+        },
+        _ => ANSI16[usize::from(value)].1,
+    }
+}
+
+const ANSI16: [(Color, (u8, u8, u8)); 16] = [
+    (Color::Black, (0, 0, 0)),
+    (Color::DarkRed, (128, 0, 0)),
+    (Color::DarkGreen, (0, 128, 0)),
+    (Color::DarkYellow, (128, 128, 0)),
+    (Color::DarkBlue, (0, 0, 128)),
+    (Color::DarkMagenta, (128, 0, 128)),
+    (Color::DarkCyan, (0, 128, 128)),
+    (Color::Grey, (192, 192, 192)),
+    (Color::DarkGrey, (128, 128, 128)),
+    (Color::Red, (255, 0, 0)),
+    (Color::Green, (0, 255, 0)),
+    (Color::Yellow, (255, 255, 0)),
+    (Color::Blue, (0, 0, 255)),
+    (Color::Magenta, (255, 0, 255)),
+    (Color::Cyan, (0, 255, 255)),
+    (Color::White, (255, 255, 255)),
+];
+
+fn nearest_ansi16(r: u8, g: u8, b: u8) -> Color {
+    let distance = |(r2, g2, b2): (u8, u8, u8)| {
+        let dr = i32::from(r) - i32::from(r2);
+        let dg = i32::from(g) - i32::from(g2);
+        let db = i32::from(b) - i32::from(b2);
+
+        #[allow(clippy::integer_arithmetic)]
+        let distance = dr * dr + dg * dg + db * db;
+        distance
+    };
+
+    ANSI16
+        .iter()
+        .min_by_key(|(_, rgb)| distance(*rgb))
+        .map_or(Color::Reset, |(color, _)| *color)
+}
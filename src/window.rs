@@ -0,0 +1,212 @@
+use std::mem;
+
+use crate::Position;
+
+/// How much a window grows or shrinks at once, in percent of the split it is in.
+const RESIZE_STEP: usize = 5;
+
+#[derive(Default, Clone, Copy)]
+pub struct Area {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+#[derive(Default, Clone)]
+pub struct Window {
+    pub buffer: usize,
+    pub cursor_position: Position,
+    pub selection_anchor: Option<Position>,
+    pub offset: Position,
+    pub offset_wrap: usize,
+    pub area: Area,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum Direction {
+    /// Stacked on top of each other.
+    Down,
+    /// Side by side, separated by a column.
+    Right,
+}
+
+/// How the screen is divided between the windows, which are referred to by index.
+pub enum Layout {
+    Window(usize),
+    Split {
+        direction: Direction,
+        /// The share of the space given to `first`, in percent.
+        share: usize,
+        first: Box<Layout>,
+        second: Box<Layout>,
+    },
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Self::Window(0)
+    }
+}
+
+impl Layout {
+    pub fn windows(&self) -> Vec<usize> {
+        match self {
+            Self::Window(window) => vec![*window],
+            Self::Split { first, second, .. } => {
+                let mut windows = first.windows();
+                windows.extend(second.windows());
+                windows
+            },
+        }
+    }
+
+    /// Divides `area` between the windows, returning the area of each window along with the
+    /// columns separating windows side by side.
+    pub fn arrange(&self, area: Area) -> (Vec<(usize, Area)>, Vec<Area>) {
+        let mut windows = Vec::new();
+        let mut separators = Vec::new();
+        self.arrange_into(area, &mut windows, &mut separators);
+
+        (windows, separators)
+    }
+
+    #[allow(clippy::integer_arithmetic, clippy::integer_division)]
+    fn arrange_into(
+        &self,
+        area: Area,
+        windows: &mut Vec<(usize, Area)>,
+        separators: &mut Vec<Area>,
+    ) {
+        match self {
+            Self::Window(window) => windows.push((*window, area)),
+            Self::Split {
+                direction: Direction::Down,
+                share,
+                first,
+                second,
+            } => {
+                // Every window keeps a line of text above its status bar where there is room
+                let height = (area.height * share / 100)
+                    .clamp(2, area.height.saturating_sub(2).max(2))
+                    .min(area.height);
+                first.arrange_into(Area { height, ..area }, windows, separators);
+                second.arrange_into(
+                    Area {
+                        y: area.y + height,
+                        height: area.height - height,
+                        ..area
+                    },
+                    windows,
+                    separators,
+                );
+            },
+            Self::Split {
+                direction: Direction::Right,
+                share,
+                first,
+                second,
+            } => {
+                let width = (area.width * share / 100)
+                    .clamp(1, area.width.saturating_sub(2).max(1))
+                    .min(area.width);
+                first.arrange_into(Area { width, ..area }, windows, separators);
+                separators.push(Area {
+                    x: area.x + width,
+                    width: area.width.saturating_sub(width).min(1),
+                    ..area
+                });
+                second.arrange_into(
+                    Area {
+                        x: area.x + width + 1,
+                        width: area.width.saturating_sub(width + 1),
+                        ..area
+                    },
+                    windows,
+                    separators,
+                );
+            },
+        }
+    }
+
+    pub fn split(&mut self, window: usize, new: usize, direction: Direction) {
+        match self {
+            Self::Window(index) if *index == window => {
+                *self = Self::Split {
+                    direction,
+                    share: 50,
+                    first: Box::new(Self::Window(window)),
+                    second: Box::new(Self::Window(new)),
+                };
+            },
+            Self::Window(_) => (),
+            Self::Split { first, second, .. } => {
+                first.split(window, new, direction);
+                second.split(window, new, direction);
+            },
+        }
+    }
+
+    /// Removes `window`, giving its space to its neighbour, and renumbers the windows after it.
+    /// The last window is never removed.
+    pub fn remove(&mut self, window: usize) {
+        self.take_out(window);
+        self.renumber(window);
+    }
+
+    fn take_out(&mut self, window: usize) {
+        if let Self::Split { first, second, .. } = self {
+            if matches!(**first, Self::Window(index) if index == window) {
+                *self = mem::take(&mut **second);
+            } else if matches!(**second, Self::Window(index) if index == window) {
+                *self = mem::take(&mut **first);
+            } else {
+                first.take_out(window);
+                second.take_out(window);
+            }
+        }
+    }
+
+    fn renumber(&mut self, removed: usize) {
+        match self {
+            Self::Window(index) if *index > removed => *index = index.saturating_sub(1),
+            Self::Window(_) => (),
+            Self::Split { first, second, .. } => {
+                first.renumber(removed);
+                second.renumber(removed);
+            },
+        }
+    }
+
+    /// Makes `window` take up more or less of the innermost split it is in, returning whether
+    /// it is in one.
+    pub fn resize(&mut self, window: usize, grow: bool) -> bool {
+        let Self::Split {
+            share,
+            first,
+            second,
+            ..
+        } = self
+        else {
+            return false;
+        };
+
+        if first.resize(window, grow) || second.resize(window, grow) {
+            return true;
+        }
+
+        let in_first = first.windows().contains(&window);
+        if !in_first && !second.windows().contains(&window) {
+            return false;
+        }
+
+        *share = if in_first == grow {
+            share.saturating_add(RESIZE_STEP)
+        } else {
+            share.saturating_sub(RESIZE_STEP)
+        }
+        .clamp(RESIZE_STEP, 100_usize.saturating_sub(RESIZE_STEP));
+
+        true
+    }
+}